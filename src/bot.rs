@@ -1,24 +1,56 @@
-use std::fmt::Write;
+use std::{fmt::Write, time::Duration};
 
+use chrono::Utc;
 use teloxide::{
-    adaptors::{throttle::Limits, CacheMe, DefaultParseMode, Throttle},
+    adaptors::{throttle::Limits, CacheMe, Throttle},
     prelude::*,
     types::{
-        AllowedUpdate, BotCommandScope, ChatKind, ChatMember, ChatMemberKind, ChatPublic,
-        LinkPreviewOptions, MessageId, MessageKind, ParseMode, PublicChatKind, Recipient,
-        ReplyParameters, Update,
+        AllowedUpdate, BotCommandScope, CallbackQuery, Chat, ChatKind, ChatMember, ChatMemberKind,
+        ChatMemberUpdated, ChatMigration, ChatPublic, LinkPreviewOptions, MessageId, MessageKind,
+        ParseMode, PublicChatKind, Recipient, ReplyParameters, ThreadId, Update,
     },
     update_listeners::{webhooks, Polling},
     utils::{command::BotCommands, markdown},
-    Bot,
+    Bot, RequestError,
 };
-use tracing::{debug, error, warn};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info, warn};
 
-use crate::{config::BotConfig, storage::Storage, utils::DisplayMessageKind};
+use crate::{
+    config::{BotConfig, RevalidateConfig, Timezone},
+    storage::{Member, PingPolicy, Storage},
+    utils::DisplayMessageKind,
+};
+
+mod admins;
+mod confirm;
+mod escalation;
+mod limits;
+mod ping;
+mod preview;
+mod private;
+mod render;
+mod roster;
+mod scheduler;
+mod triggers;
+
+use admins::{AdminCache, AdminCalls};
+use confirm::{PendingPings, MAX_CONFIRM_ABOVE};
+use escalation::{
+    run_escalations, MAX_ESCALATION_INTERVAL, MAX_ESCALATION_ROUNDS, MIN_ESCALATION_INTERVAL,
+};
+use limits::{format_duration, parse_duration, ping_allowed, MAX_COOLDOWN};
+use ping::{group_name, mentions_cap, parse_ping_args, parse_urgent, ping_targets, Call, ALL};
+use private::{private_command_handler, PrivateCommand};
+use render::MAX_ENTITIES;
+use scheduler::{add_scheduled_ping, parse_time, run_scheduler, Schedule};
+use triggers::{strip_trigger, trigger_phrase, MAX_TRIGGERS};
 
 const GIT: &str = "github.com/lattenwald/tgpingerbot";
+/// `/ping_active` without arguments pings those active within that many days
+const DEFAULT_ACTIVE_DAYS: u32 = 14;
 
-pub type MyBot = Throttle<CacheMe<DefaultParseMode<Bot>>>;
+pub type MyBot = Throttle<CacheMe<Bot>>;
 pub type MyDispatcher =
     Dispatcher<MyBot, teloxide::RequestError, teloxide::dispatching::DefaultKey>;
 
@@ -27,7 +59,6 @@ pub async fn start_bot(
     storage: Storage,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let bot = Bot::new(config.token.clone())
-        .parse_mode(ParseMode::MarkdownV2)
         .cache_me()
         .throttle(Limits::default());
 
@@ -39,6 +70,14 @@ pub async fn start_bot(
         error!("failed setting commands (default scope): {}", err);
     }
 
+    if let Err(err) = bot
+        .set_my_commands(PrivateCommand::bot_commands())
+        .scope(BotCommandScope::AllPrivateChats)
+        .await
+    {
+        error!("failed setting commands (private scope): {}", err);
+    }
+
     if let Some(chat_id) = config.admin_id {
         let mut commands = Command::bot_commands();
         if let Ok(admin_chat) = bot.get_chat(ChatId(chat_id)).await {
@@ -57,32 +96,68 @@ pub async fn start_bot(
         }
     }
 
-    let handler = Update::filter_message()
-        .branch(
-            dptree::filter(move |msg: Message| {
-                config
-                    .admin_id
-                    .is_some_and(|admin_id| admin_id == msg.chat.id.0)
-            })
-            .filter_command::<Command>()
-            .endpoint(command_handler),
-        )
+    let handler = dptree::entry()
         .branch(
-            dptree::entry()
-                .filter_command::<UnauthorizedCommand>()
-                .endpoint(unauthorized_command_handler),
+            Update::filter_message()
+                .branch(
+                    dptree::filter(move |msg: Message| {
+                        config
+                            .admin_id
+                            .is_some_and(|admin_id| admin_id == msg.chat.id.0)
+                    })
+                    .filter_command::<Command>()
+                    .endpoint(command_handler),
+                )
+                .branch(
+                    dptree::filter(|msg: Message| msg.chat.is_private())
+                        .filter_command::<PrivateCommand>()
+                        .endpoint(private_command_handler),
+                )
+                .branch(
+                    dptree::entry()
+                        .filter_command::<UnauthorizedCommand>()
+                        .endpoint(unauthorized_command_handler),
+                )
+                .branch(
+                    dptree::entry()
+                        .filter(|_msg: Message| true)
+                        .endpoint(message_handler),
+                ),
         )
-        .branch(
-            dptree::entry()
-                .filter(|_msg: Message| true)
-                .endpoint(message_handler),
-        );
+        .branch(Update::filter_chat_member().endpoint(chat_member_handler))
+        .branch(Update::filter_callback_query().endpoint(callback_handler));
+
+    if let Some(revalidate) = config.revalidate.clone() {
+        tokio::spawn(revalidate_members(bot.clone(), storage.clone(), revalidate));
+    }
+
+    tokio::spawn(run_escalations(
+        bot.clone(),
+        storage.clone(),
+        config.timezone.0,
+    ));
+
+    tokio::spawn(run_scheduler(
+        bot.clone(),
+        storage.clone(),
+        config.timezone.0,
+    ));
 
     let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
-        .dependencies(dptree::deps![storage])
+        .dependencies(dptree::deps![
+            storage,
+            config.timezone,
+            AdminCache::default(),
+            AdminCalls::default(),
+            PendingPings::default()
+        ])
         .build();
 
-    let allowed_updates = vec![AllowedUpdate::Message, AllowedUpdate::ChatMember];
+    let allowed_updates = vec![
+        AllowedUpdate::Message,
+        AllowedUpdate::ChatMember,
+        AllowedUpdate::CallbackQuery,
+    ];
     if let Some(webhook_config) = config.webhook {
         let mut url = webhook_config.url.clone();
         {
@@ -90,11 +165,15 @@ pub async fn start_bot(
             path.push(&format!("bot{}", config.token));
         }
         debug!("webhook url: {}", &url);
-        let listener = webhooks::axum(
-            bot.clone(),
-            webhooks::Options::new(webhook_config.address, url),
-        )
-        .await?;
+        let mut options = webhooks::Options::new(webhook_config.address, url.clone());
+        let secret = options.get_or_gen_secret_token().to_owned();
+        let listener = webhooks::axum(bot.clone(), options).await?;
+        // `webhooks::axum` does not pass allowed updates, and `chat_member` ones
+        // are only delivered when requested explicitly
+        bot.set_webhook(url)
+            .secret_token(secret)
+            .allowed_updates(allowed_updates)
+            .await?;
         let webhook_info = bot.get_webhook_info().await?;
         debug!("webhook info: {:#?}", webhook_info);
         let error_handler =
@@ -123,12 +202,106 @@ enum UnauthorizedCommand {
     #[command(description = "id текущего чата")]
     Id,
 
-    #[command(description = "пингануть всех")]
-    Ping,
+    #[command(
+        description = "пингануть группу или всех: /ping [!] [группа|all] [текст], ! — и тех, у кого тихие часы"
+    )]
+    Ping(String),
+
+    #[command(
+        description = "кого и в скольких сообщениях пинганул бы /ping, ответ в личку: /ping_preview [!] [группа|all]"
+    )]
+    PingPreview(String),
+
+    #[command(description = "пингануть тех, кто писал за последние дни: /ping_active [дней]")]
+    PingActive(String),
+
+    #[command(description = "позвать админов чата: /ping_admins [!] [текст]")]
+    PingAdmins(String),
+
+    #[command(description = "пингануть в указанное время: /ping_at 18:30 [группа|all] [текст]")]
+    PingAt(String),
+
+    #[command(
+        description = "пинговать по расписанию: /ping_every weekdays|weekends|daily|mon,thu 10:00 [группа|all] [текст]"
+    )]
+    PingEvery(String),
+
+    #[command(description = "пинги по расписанию в этом чате")]
+    Schedules,
+
+    #[command(description = "отменить пинг по расписанию: /unschedule <номер>")]
+    Unschedule(String),
+
+    #[command(description = "вступить в группу: /join <группа>")]
+    Join(String),
+
+    #[command(description = "выйти из группы: /leave <группа>")]
+    Leave(String),
+
+    #[command(description = "группы этого чата")]
+    Groups,
+
+    #[command(description = "не пинговать меня в этом чате")]
+    MuteMe,
+
+    #[command(description = "снова пинговать меня в этом чате")]
+    UnmuteMe,
+
+    #[command(description = "в теме форума: пинговать меня по /ping в этой теме")]
+    FollowTopic,
+
+    #[command(description = "в теме форума: перестать пинговать меня по /ping в этой теме")]
+    UnfollowTopic,
 
     #[command(description = "сколько тут юзеров, кого пингуем")]
     Count,
 
+    #[command(description = "кого пингуем, списком без упоминаний")]
+    Who,
+
+    #[command(description = "настройки чата")]
+    Settings,
+
+    #[command(description = "пауза между пингами в чате, для админов: /set_cooldown 5m|0")]
+    SetCooldown(String),
+
+    #[command(description = "пингов в сутки на человека, для админов: /set_quota 3|0")]
+    SetQuota(String),
+
+    #[command(
+        description = "повторно пинговать не нажавших кнопку, для админов: /set_escalation 10m 2|0"
+    )]
+    SetEscalation(String),
+
+    #[command(
+        description = "сколько упоминаний в одном сообщении пинга, для админов: /set_mentions 20|0 (0 — по умолчанию, 40)"
+    )]
+    SetMentions(String),
+
+    #[command(
+        description = "спрашивать подтверждение, если пинг больше N человек, для админов: /set_confirm 50|0"
+    )]
+    SetConfirm(String),
+
+    #[command(
+        description = "кто может пинговать, для админов: /ping_policy all|admins|list (list — админы и разрешённые)"
+    )]
+    PingPolicy(String),
+
+    #[command(
+        description = "фраза, которая пингует как /ping, если её написать, для админов: /add_trigger @all"
+    )]
+    AddTrigger(String),
+
+    #[command(description = "убрать такую фразу, для админов: /remove_trigger @all")]
+    RemoveTrigger(String),
+
+    #[command(description = "разрешить пинговать, для админов: ответом на сообщение пользователя")]
+    AllowPing,
+
+    #[command(description = "запретить пинговать, для админов: ответом на сообщение пользователя")]
+    DisallowPing,
+
     #[command(description = "помощь")]
     Help,
 }
@@ -149,26 +322,64 @@ enum Command {
     Help,
 }
 
+/// `/ping` with `args`, also sent by writing trigger phrase in chat. Pings of
+/// more people than chat allows at once wait for caller to confirm them.
+async fn ping_command(
+    bot: &MyBot,
+    storage: &Storage,
+    pending: &PendingPings,
+    msg: &Message,
+    tz: Timezone,
+    args: &str,
+) {
+    if !ping_allowed(bot, storage, msg).await {
+        return;
+    }
+    let reply_to_msg_id = msg.reply_to_message().map(|msg| msg.id).unwrap_or(msg.id);
+    let (urgent, args) = parse_urgent(args);
+    let (group, text) = match parse_ping_args(storage, msg.chat.id, args).await {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            reply(bot, msg, &err.to_string()).await;
+            return;
+        }
+    };
+    let members = ping_targets(storage, Place::of(msg), group.as_deref())
+        .await
+        .unwrap();
+    let call = Call {
+        caller: msg.from.as_ref(),
+        text,
+        urgent,
+        tz: tz.0,
+        limited: true,
+    };
+    pending
+        .ping(bot, storage, Place::of(msg), reply_to_msg_id, call, members)
+        .await;
+}
+
 #[tracing::instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
 async fn unauthorized_command_handler(
     bot: MyBot,
     msg: Message,
     cmd: UnauthorizedCommand,
     storage: Storage,
+    tz: Timezone,
+    admins: AdminCache,
+    admin_calls: AdminCalls,
+    pending: PendingPings,
 ) -> ResponseResult<()> {
     debug!("unauthorized command: {:?}", cmd);
     if let Some(ref from) = msg.from {
-        let _ = storage.new_member(&msg.chat, from).await;
+        let _ = storage
+            .member_seen(&msg.chat, from, msg.date.timestamp())
+            .await;
     }
     match cmd {
         UnauthorizedCommand::Id => {
-            if let Err(err) = bot
-                .send_message(msg.chat.id, format!("`{}`", msg.chat.id))
-                .reply_parameters(ReplyParameters::new(msg.id))
-                .await
-            {
-                warn!("failed sending message: {:?}", err);
-            }
+            reply(&bot, &msg, &format!("`{}`", msg.chat.id)).await;
         }
         UnauthorizedCommand::Help => {
             let help = format!(
@@ -177,81 +388,538 @@ async fn unauthorized_command_handler(
                 markdown::escape(GIT),
                 markdown::escape(GIT),
             );
-            reply(&bot, msg.chat.id, msg.id, &help).await;
+            reply(&bot, &msg, &help).await;
+        }
+        UnauthorizedCommand::Ping(args) => {
+            ping_command(&bot, &storage, &pending, &msg, tz, &args).await;
         }
-        UnauthorizedCommand::Ping => {
+        UnauthorizedCommand::PingActive(days) => {
             let reply_to_msg_id = msg.reply_to_message().map(|msg| msg.id).unwrap_or(msg.id);
-            let members = storage.chat_members(msg.chat.id).await.unwrap();
-
-            let mut buf = if let Some(u) = msg.from.as_ref() {
-                format!(
-                    "{} вызывает\\!\n\n",
-                    u.username.as_ref().map_or(
-                        format!(
-                            "[{}](tg://user?id={})",
-                            markdown::escape(&u.full_name()),
-                            u.id
-                        ),
-                        |n| { format!("@{}", markdown::escape(n)) }
-                    )
-                )
+            let days = days.trim();
+            let days = if days.is_empty() {
+                DEFAULT_ACTIVE_DAYS
             } else {
-                String::new()
-            };
-
-            let mut count = 0;
-            let mut total = 0;
-            for member in members {
-                if member.is_bot {
-                    continue;
-                }
-                if msg
-                    .from
-                    .as_ref()
-                    .is_some_and(|f| f.id.0.to_string() == member.user_id)
-                {
-                    continue;
+                match days.parse::<u32>() {
+                    Ok(days) if days > 0 => days,
+                    _ => {
+                        reply(&bot, &msg, "Неправильное число дней").await;
+                        return Ok(());
+                    }
                 }
-                let mention = match member.username {
-                    Some(username) => format!("@{}", markdown::escape(&username)),
-                    None => format!(
-                        "[{}](tg://user?id={})",
-                        markdown::escape(&member.full_name()),
-                        member.user_id
+            };
+            if !ping_allowed(&bot, &storage, &msg).await {
+                return Ok(());
+            }
+            let since = msg.date.timestamp() - i64::from(days) * 24 * 60 * 60;
+            let members = storage.active_members(msg.chat.id, since).await.unwrap();
+            pending
+                .ping(
+                    &bot,
+                    &storage,
+                    Place::of(&msg),
+                    reply_to_msg_id,
+                    Call {
+                        caller: msg.from.as_ref(),
+                        text: "",
+                        urgent: false,
+                        tz: tz.0,
+                        limited: true,
+                    },
+                    members,
+                )
+                .await;
+        }
+        UnauthorizedCommand::PingAdmins(text) => {
+            // whatever chat policy and limits are, so that spam can be reported
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            if let Err(wait) = admin_calls.call(msg.chat.id, from.id) {
+                reply(
+                    &bot,
+                    &msg,
+                    &format!(
+                        "Админов уже звали, позвать снова можно через `{}`",
+                        format_duration(wait.as_secs() as i64 + 1)
                     ),
-                };
-                let _ = write!(buf, " {}", mention);
-                count += 1;
-                total += 1;
-
-                if count >= 40 {
-                    reply(&bot, msg.chat.id, reply_to_msg_id, &buf).await;
-                    buf.clear();
-                    count = 0;
+                )
+                .await;
+                return Ok(());
+            }
+            let reply_to_msg_id = msg.reply_to_message().map(|msg| msg.id).unwrap_or(msg.id);
+            let (urgent, text) = parse_urgent(&text);
+            let members = match admins.get(&bot, msg.chat.id).await {
+                Ok(admins) => admins.iter().map(Member::from).collect(),
+                Err(err) => {
+                    error!("failed getting chat administrators: {}", err);
+                    reply(&bot, &msg, "Не получилось узнать админов").await;
+                    return Ok(());
                 }
+            };
+            pending
+                .ping(
+                    &bot,
+                    &storage,
+                    Place::of(&msg),
+                    reply_to_msg_id,
+                    Call {
+                        caller: Some(from),
+                        text,
+                        urgent,
+                        tz: tz.0,
+                        limited: false,
+                    },
+                    members,
+                )
+                .await;
+        }
+        UnauthorizedCommand::PingAt(args) => {
+            if !ping_allowed(&bot, &storage, &msg).await {
+                return Ok(());
+            }
+            let (time, rest) = split_word(&args);
+            let Some(time) = parse_time(time) else {
+                reply(&bot, &msg, "Неправильное время").await;
+                return Ok(());
+            };
+            let next_run = Schedule::daily(time).next_after(Utc::now(), tz.0);
+            add_scheduled_ping(&bot, &storage, &pending, &msg, tz.0, None, next_run, rest).await;
+        }
+        UnauthorizedCommand::PingEvery(args) => {
+            if !ping_allowed(&bot, &storage, &msg).await {
+                return Ok(());
+            }
+            let (days, rest) = split_word(&args);
+            let (time, rest) = split_word(rest);
+            let Ok(schedule) = format!("{} {}", days, time).parse::<Schedule>() else {
+                reply(&bot, &msg, "Неправильное расписание").await;
+                return Ok(());
+            };
+            let next_run = schedule.next_after(Utc::now(), tz.0);
+            add_scheduled_ping(
+                &bot,
+                &storage,
+                &pending,
+                &msg,
+                tz.0,
+                Some(schedule),
+                next_run,
+                rest,
+            )
+            .await;
+        }
+        UnauthorizedCommand::Schedules => {
+            let pings = storage.chat_scheduled_pings(msg.chat.id).await.unwrap();
+            if pings.is_empty() {
+                reply(&bot, &msg, "В этом чате нет пингов по расписанию").await;
+                return Ok(());
+            }
+            let mut buf = "Пинги по расписанию:\n".to_string();
+            for ping in pings {
+                let _ = write!(buf, "\n{}\n", scheduler::describe(&ping, tz.0));
             }
-            if total == 0 {
+            reply(&bot, &msg, &buf).await;
+        }
+        UnauthorizedCommand::Unschedule(id) => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            let Ok(id) = id.trim().parse::<i64>() else {
+                reply(&bot, &msg, "Неправильный номер").await;
+                return Ok(());
+            };
+            let pings = storage.chat_scheduled_pings(msg.chat.id).await.unwrap();
+            let Some(ping) = pings.into_iter().find(|ping| ping.id == id) else {
+                reply(&bot, &msg, "Нет такого пинга").await;
+                return Ok(());
+            };
+            if ping.created_by != from.id.to_string()
+                && !is_chat_admin(&bot, msg.chat.id, from.id).await
+            {
                 reply(
                     &bot,
-                    msg.chat.id,
-                    reply_to_msg_id,
-                    "Тут нет никого, кроме нас",
+                    &msg,
+                    "Отменить пинг может только тот, кто его запланировал, или админ",
                 )
                 .await;
-            } else if count > 0 {
-                reply(&bot, msg.chat.id, reply_to_msg_id, &buf).await;
+                return Ok(());
+            }
+            let text = match storage.delete_scheduled_ping(msg.chat.id, id).await {
+                Ok(_) => format!("Отменил пинг `{}`", id),
+                Err(err) => {
+                    error!("failed deleting scheduled ping: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
+        UnauthorizedCommand::Join(group) => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            let Some(group) = group_name(&group) else {
+                reply(&bot, &msg, "Неправильное имя группы").await;
+                return Ok(());
+            };
+            let text = match storage.join_group(msg.chat.id, from.id, &group).await {
+                Ok(true) => format!("Добавил в группу `{}`", markdown::escape(&group)),
+                Ok(false) => format!("Уже в группе `{}`", markdown::escape(&group)),
+                Err(err) => {
+                    error!("failed joining group: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
+        UnauthorizedCommand::Leave(group) => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            let Some(group) = group_name(&group) else {
+                reply(&bot, &msg, "Неправильное имя группы").await;
+                return Ok(());
+            };
+            let text = match storage.leave_group(msg.chat.id, from.id, &group).await {
+                Ok(true) => format!("Убрал из группы `{}`", markdown::escape(&group)),
+                Ok(false) => format!("Не в группе `{}`", markdown::escape(&group)),
+                Err(err) => {
+                    error!("failed leaving group: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
+        UnauthorizedCommand::Groups => {
+            let groups = storage.chat_groups(msg.chat.id).await.unwrap();
+            if groups.is_empty() {
+                reply(&bot, &msg, "В этом чате нет групп").await;
+                return Ok(());
+            }
+            let mut buf = "Группы этого чата:\n\n".to_string();
+            for (group, count) in groups {
+                let _ = writeln!(buf, "`{}`: `{}`", markdown::escape(&group), count);
             }
+            reply(&bot, &msg, &buf).await;
+        }
+        UnauthorizedCommand::MuteMe | UnauthorizedCommand::UnmuteMe => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            let muted = matches!(cmd, UnauthorizedCommand::MuteMe);
+            let text = match storage.set_muted(msg.chat.id, from.id, muted).await {
+                Ok(true) if muted => "Больше не пингую тебя в этом чате",
+                Ok(false) if muted => "Уже не пингую тебя в этом чате",
+                Ok(true) => "Снова пингую тебя в этом чате",
+                Ok(false) => "И так пингую тебя в этом чате",
+                Err(err) => {
+                    error!("failed setting muted: {}", err);
+                    "Не получилось"
+                }
+            };
+            reply(&bot, &msg, text).await;
+        }
+        UnauthorizedCommand::FollowTopic | UnauthorizedCommand::UnfollowTopic => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            let Some(thread_id) = topic(&msg) else {
+                reply(&bot, &msg, "Это работает только в темах форума").await;
+                return Ok(());
+            };
+            let follow = matches!(cmd, UnauthorizedCommand::FollowTopic);
+            let text = match storage
+                .set_topic_follower(msg.chat.id, thread_id, from.id, follow)
+                .await
+            {
+                Ok(true) if follow => "Буду пинговать тебя по /ping в этой теме",
+                Ok(false) if follow => "Уже пингую тебя по /ping в этой теме",
+                Ok(true) => "Больше не пингую тебя по /ping в этой теме",
+                Ok(false) => "И так не пингую тебя по /ping в этой теме",
+                Err(err) => {
+                    error!("failed setting topic follower: {}", err);
+                    "Не получилось"
+                }
+            };
+            reply(&bot, &msg, &markdown::escape(text)).await;
         }
         UnauthorizedCommand::Count => {
             let count = storage.chat_members_count(msg.chat.id).await.unwrap();
             reply(
                 &bot,
-                msg.chat.id,
-                msg.id,
+                &msg,
                 &format!("В этом чате пингую `{}` пользователей", count),
             )
             .await;
         }
+        UnauthorizedCommand::Who => {
+            roster::who(&bot, &storage, &msg).await;
+        }
+        UnauthorizedCommand::PingPreview(ref args) => {
+            preview::ping_preview(&bot, &storage, &msg, tz.0, args).await;
+        }
+        UnauthorizedCommand::Settings => {
+            let settings = storage.chat_settings(msg.chat.id).await.unwrap();
+            let cooldown = match settings.ping_cooldown {
+                0 => "нет".to_string(),
+                secs => format_duration(secs),
+            };
+            let quota = match settings.daily_quota {
+                0 => "без ограничений".to_string(),
+                quota => quota.to_string(),
+            };
+            let policy = match settings.ping_policy {
+                PingPolicy::All => "все".to_string(),
+                PingPolicy::Admins => "админы".to_string(),
+                PingPolicy::Allowlist => {
+                    let allowed = storage.ping_allowlist(msg.chat.id).await.unwrap();
+                    let names: Vec<String> = allowed
+                        .iter()
+                        .map(|member| markdown::escape(&member.full_name()))
+                        .collect();
+                    if names.is_empty() {
+                        "админы".to_string()
+                    } else {
+                        format!("админы, {}", names.join(", "))
+                    }
+                }
+            };
+            let escalation = match settings.escalation_interval {
+                0 => "нет".to_string(),
+                secs => format!(
+                    "`{}` раз через `{}`",
+                    settings.escalation_rounds,
+                    format_duration(secs)
+                ),
+            };
+            let confirm = match settings.confirm_above {
+                0 => "нет".to_string(),
+                size => format!("для пингов больше `{}` человек", size),
+            };
+            let triggers = storage.ping_triggers(msg.chat.id).await.unwrap();
+            let triggers = if triggers.is_empty() {
+                "нет".to_string()
+            } else {
+                triggers
+                    .iter()
+                    .map(|phrase| format!("`{}`", markdown::escape_code(phrase)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            reply(&bot, &msg, &format!(
+                    "Настройки чата:\n\nПауза между пингами: `{}`\nПингов в сутки на человека: `{}`\nПинговать могут: {}\nПовторный пинг не ответивших: {}\nУпоминаний в сообщении: `{}`\nПодтверждение пинга: {}\nФразы, которые пингуют: {}",
+                    cooldown, quota, policy, escalation, mentions_cap(&settings), confirm, triggers
+                ),
+            )
+            .await;
+        }
+        UnauthorizedCommand::AddTrigger(ref phrase)
+        | UnauthorizedCommand::RemoveTrigger(ref phrase) => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            if !is_chat_admin(&bot, msg.chat.id, from.id).await {
+                reply(&bot, &msg, "Это могут только админы чата").await;
+                return Ok(());
+            }
+            let Some(phrase) = trigger_phrase(phrase) else {
+                reply(&bot, &msg, "Неправильная фраза").await;
+                return Ok(());
+            };
+            let add = matches!(cmd, UnauthorizedCommand::AddTrigger(_));
+            if add && storage.ping_triggers(msg.chat.id).await.unwrap().len() >= MAX_TRIGGERS {
+                reply(
+                    &bot,
+                    &msg,
+                    &format!("Больше `{}` фраз нельзя", MAX_TRIGGERS),
+                )
+                .await;
+                return Ok(());
+            }
+            let escaped = markdown::escape(&phrase);
+            let text = match storage.set_ping_trigger(msg.chat.id, &phrase, add).await {
+                Ok(true) if add => format!("Теперь «{}» пингует как /ping", escaped),
+                Ok(false) if add => format!("«{}» уже пингует", escaped),
+                Ok(true) => format!("«{}» больше не пингует", escaped),
+                Ok(false) => format!("«{}» и так не пингует", escaped),
+                Err(err) => {
+                    error!("failed setting ping trigger: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
+        UnauthorizedCommand::AllowPing | UnauthorizedCommand::DisallowPing => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            if !is_chat_admin(&bot, msg.chat.id, from.id).await {
+                reply(&bot, &msg, "Это могут только админы чата").await;
+                return Ok(());
+            }
+            let Some(user) = msg.reply_to_message().and_then(|msg| msg.from.as_ref()) else {
+                reply(&bot, &msg, "Ответь этой командой на сообщение пользователя").await;
+                return Ok(());
+            };
+            let _ = storage.new_member(&msg.chat, user).await;
+            let allowed = matches!(cmd, UnauthorizedCommand::AllowPing);
+            let name = markdown::escape(&user.full_name());
+            let text = match storage
+                .set_ping_allowed(msg.chat.id, user.id, allowed)
+                .await
+            {
+                Ok(true) if allowed => format!("{} теперь может пинговать", name),
+                Ok(false) if allowed => format!("{} уже может пинговать", name),
+                Ok(true) => format!("{} больше не может пинговать", name),
+                Ok(false) => format!("{} и так не было в списке", name),
+                Err(err) => {
+                    error!("failed setting ping allowed: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
+        UnauthorizedCommand::SetCooldown(_)
+        | UnauthorizedCommand::SetQuota(_)
+        | UnauthorizedCommand::SetEscalation(_)
+        | UnauthorizedCommand::SetMentions(_)
+        | UnauthorizedCommand::SetConfirm(_)
+        | UnauthorizedCommand::PingPolicy(_) => {
+            let Some(ref from) = msg.from else {
+                return Ok(());
+            };
+            if !is_chat_admin(&bot, msg.chat.id, from.id).await {
+                reply(&bot, &msg, "Это могут только админы чата").await;
+                return Ok(());
+            }
+            let mut settings = storage.chat_settings(msg.chat.id).await.unwrap();
+            let text = match cmd {
+                UnauthorizedCommand::SetCooldown(value) => match parse_duration(&value) {
+                    Some(secs) if secs <= MAX_COOLDOWN => {
+                        settings.ping_cooldown = secs;
+                        match secs {
+                            0 => "Пауза между пингами убрана".to_string(),
+                            secs => format!("Пауза между пингами `{}`", format_duration(secs)),
+                        }
+                    }
+                    _ => {
+                        reply(&bot, &msg, "Неправильная пауза, можно от `0` до `24h`").await;
+                        return Ok(());
+                    }
+                },
+                UnauthorizedCommand::SetQuota(value) => match value.trim().parse::<u32>() {
+                    Ok(quota) => {
+                        settings.daily_quota = i64::from(quota);
+                        match quota {
+                            0 => "Ограничение пингов в сутки убрано".to_string(),
+                            quota => format!("Не больше `{}` пингов в сутки на человека", quota),
+                        }
+                    }
+                    Err(_) => {
+                        reply(&bot, &msg, "Неправильное число пингов").await;
+                        return Ok(());
+                    }
+                },
+                UnauthorizedCommand::SetEscalation(value) => {
+                    let (interval, rounds) = split_word(&value);
+                    match (parse_duration(interval), rounds.parse::<i64>()) {
+                        (Some(0), _) => {
+                            settings.escalation_interval = 0;
+                            settings.escalation_rounds = 0;
+                            "Повторный пинг выключен".to_string()
+                        }
+                        (Some(secs), Ok(rounds))
+                            if (MIN_ESCALATION_INTERVAL..=MAX_ESCALATION_INTERVAL)
+                                .contains(&secs)
+                                && (1..=MAX_ESCALATION_ROUNDS).contains(&rounds) =>
+                        {
+                            settings.escalation_interval = secs;
+                            settings.escalation_rounds = rounds;
+                            format!(
+                                "Не нажавших кнопку пингую ещё `{}` раз через `{}`, потом называю их",
+                                rounds,
+                                format_duration(secs)
+                            )
+                        }
+                        _ => {
+                            reply(&bot, &msg, &format!(
+                                    "Нужны интервал от `1m` до `24h` и число повторов от `1` до `{}`, или `0`, чтобы выключить",
+                                    MAX_ESCALATION_ROUNDS
+                                ),
+                            )
+                            .await;
+                            return Ok(());
+                        }
+                    }
+                }
+                UnauthorizedCommand::SetMentions(value) => match value.trim().parse::<usize>() {
+                    Ok(cap) if cap <= MAX_ENTITIES => {
+                        settings.mentions_per_message = cap as i64;
+                        format!(
+                            "Упоминаний в одном сообщении: `{}`",
+                            mentions_cap(&settings)
+                        )
+                    }
+                    _ => {
+                        reply(
+                            &bot,
+                            &msg,
+                            &format!("Можно от `1` до `{}`, или `0` по умолчанию", MAX_ENTITIES),
+                        )
+                        .await;
+                        return Ok(());
+                    }
+                },
+                UnauthorizedCommand::SetConfirm(value) => match value.trim().parse::<u32>() {
+                    Ok(size) if size <= MAX_CONFIRM_ABOVE => {
+                        settings.confirm_above = i64::from(size);
+                        match size {
+                            0 => "Пинги отправляются без подтверждения".to_string(),
+                            size => format!(
+                                "Пинг больше `{}` человек отправится после подтверждения позвавшим",
+                                size
+                            ),
+                        }
+                    }
+                    _ => {
+                        reply(
+                            &bot,
+                            &msg,
+                            &format!("Можно от `0` до `{}`", MAX_CONFIRM_ABOVE),
+                        )
+                        .await;
+                        return Ok(());
+                    }
+                },
+                UnauthorizedCommand::PingPolicy(value) => {
+                    match value.trim().to_lowercase().parse::<PingPolicy>() {
+                        Ok(policy) => {
+                            settings.ping_policy = policy;
+                            match policy {
+                                PingPolicy::All => "Теперь пинговать могут все",
+                                PingPolicy::Admins => "Теперь пинговать могут только админы",
+                                PingPolicy::Allowlist => {
+                                    "Теперь пинговать могут админы и разрешённые через /allow\\_ping"
+                                }
+                            }
+                            .to_string()
+                        }
+                        Err(_) => {
+                            reply(&bot, &msg, "Можно `all`, `admins` или `list`").await;
+                            return Ok(());
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            };
+            let text = match storage.save_chat_settings(msg.chat.id, &settings).await {
+                Ok(()) => text,
+                Err(err) => {
+                    error!("failed saving chat settings: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
     }
     Ok(())
 }
@@ -265,7 +933,9 @@ async fn command_handler(
 ) -> ResponseResult<()> {
     debug!("authorized command: {:?}", cmd);
     if let Some(ref from) = msg.from {
-        let _ = storage.new_member(&msg.chat, from).await;
+        let _ = storage
+            .member_seen(&msg.chat, from, msg.date.timestamp())
+            .await;
     }
     match cmd {
         Command::Help => {
@@ -276,32 +946,31 @@ async fn command_handler(
                 markdown::escape(GIT),
                 markdown::escape(GIT),
             );
-            reply(&bot, msg.chat.id, msg.id, &help).await;
+            reply(&bot, &msg, &help).await;
         }
         Command::AddUser(chat_id, user_id) => {
             let Ok(chat_id) = chat_id.parse::<i64>().map(ChatId) else {
-                reply(&bot, msg.chat.id, msg.id, "Неправильный id чата").await;
+                reply(&bot, &msg, "Неправильный id чата").await;
                 return Ok(());
             };
             let Ok(user_id) = user_id.parse().map(UserId) else {
-                reply(&bot, msg.chat.id, msg.id, "Неправильный id пользователя").await;
+                reply(&bot, &msg, "Неправильный id пользователя").await;
                 return Ok(());
             };
             match check_member(&bot, &storage, chat_id, user_id).await {
                 Ok(true) => {
-                    reply(&bot, msg.chat.id, msg.id, "Пользователь есть в чате").await;
+                    reply(&bot, &msg, "Пользователь есть в чате").await;
                     return Ok(());
                 }
                 Ok(false) => {
-                    reply(&bot, msg.chat.id, msg.id, "Пользователь не найден").await;
+                    reply(&bot, &msg, "Пользователь не найден").await;
                     return Ok(());
                 }
                 Err(err) => {
                     error!("failed checking member: {}", err);
                     reply(
                         &bot,
-                        msg.chat.id,
-                        msg.id,
+                        &msg,
                         &format!(
                             "Ошибка\n```\n{}\n```",
                             markdown::escape(&format!("{:#?}", err))
@@ -316,10 +985,10 @@ async fn command_handler(
             "0.1" => match storage.old_members().await {
                 Ok(members) => {
                     let mut migrated = 0;
-                    for member in members {
-                        let chat_id = ChatId(member.chat_id);
-                        let Ok(user_id) = member.user_id.parse().map(UserId) else {
-                            reply(&bot, msg.chat.id, msg.id, "Неправильный id пользователя").await;
+                    for (old_chat_id, old_user_id) in members {
+                        let chat_id = ChatId(old_chat_id);
+                        let Ok(user_id) = old_user_id.parse().map(UserId) else {
+                            reply(&bot, &msg, "Неправильный id пользователя").await;
                             continue;
                         };
                         match check_member(&bot, &storage, chat_id, user_id).await {
@@ -329,14 +998,10 @@ async fn command_handler(
                             Ok(false) => {}
                             Err(err) => {
                                 error!("failed checking member: {}", err);
-                                reply(
-                                    &bot,
-                                    msg.chat.id,
-                                    msg.id,
-                                    &format!(
+                                reply(&bot, &msg, &format!(
                                         "Ошибка проверки `chat\\_id={}` `user\\_id={}`\n```\n{}\n```",
-                                        member.chat_id,
-                                        markdown::escape(&member.user_id),
+                                        old_chat_id,
+                                        markdown::escape(&old_user_id),
                                         markdown::escape(&format!("{:#?}", err))
                                     ),
                                 ).await;
@@ -346,8 +1011,7 @@ async fn command_handler(
 
                     reply(
                         &bot,
-                        msg.chat.id,
-                        msg.id,
+                        &msg,
                         &format!("Успешно мигрировано {} пользователей", migrated),
                     )
                     .await;
@@ -356,8 +1020,7 @@ async fn command_handler(
                     error!("failed getting old members: {}", err);
                     reply(
                         &bot,
-                        msg.chat.id,
-                        msg.id,
+                        &msg,
                         &format!(
                             "Ошибка получения старых пользователей\n```\n{}\n```",
                             markdown::escape(&format!("{:#?}", err))
@@ -369,8 +1032,7 @@ async fn command_handler(
             _ => {
                 reply(
                     &bot,
-                    msg.chat.id,
-                    msg.id,
+                    &msg,
                     &format!("Нет миграции с версии `{}`", markdown::escape(&version)),
                 )
                 .await;
@@ -388,32 +1050,54 @@ async fn command_handler(
                     count
                 );
             }
-            reply(&bot, msg.chat.id, msg.id, &buf).await;
+            reply(&bot, &msg, &buf).await;
         }
     }
     Ok(())
 }
 
+/// Splits first word off `s`, both parts are trimmed
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    s.split_once(char::is_whitespace)
+        .map_or((s, ""), |(word, rest)| (word, rest.trim()))
+}
+
+/// Returns true if user is owner or administrator of chat
+async fn is_chat_admin(bot: &MyBot, chat_id: ChatId, user_id: UserId) -> bool {
+    match bot.get_chat_member(chat_id, user_id).await {
+        Ok(member) => member.kind.is_privileged(),
+        Err(err) => {
+            warn!("failed getting chat member: {}", err);
+            false
+        }
+    }
+}
+
 /// Returns true if member is in chat, false otherwise
-#[tracing::instrument(skip_all)]
 async fn check_member(
     bot: &MyBot,
     storage: &Storage,
     chat_id: ChatId,
     user_id: UserId,
 ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-    let ChatMember { user, kind } = bot.get_chat_member(chat_id, user_id).await?;
     let chat = bot.get_chat(chat_id).await?;
-    let ChatKind::Public(ChatPublic {
-        kind: ref public_chat_kind,
-        ..
-    }) = chat.kind
-    else {
-        return Ok(false);
-    };
-    if let PublicChatKind::Channel(_) = public_chat_kind {
+    check_chat_member(bot, storage, &chat, user_id).await
+}
+
+/// Same as [`check_member`], for chat fetched already. Adds member who is in
+/// chat, deletes one who is not.
+#[tracing::instrument(skip_all)]
+async fn check_chat_member(
+    bot: &MyBot,
+    storage: &Storage,
+    chat: &Chat,
+    user_id: UserId,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    if !is_group(chat) {
         return Ok(false);
     }
+    let ChatMember { user, kind } = bot.get_chat_member(chat.id, user_id).await?;
 
     let span = tracing::span!(
         tracing::Level::DEBUG,
@@ -421,22 +1105,153 @@ async fn check_member(
         kind = ?kind
     );
     let _enter = span.enter();
+    if is_in_chat(&kind) {
+        storage.new_member(chat, &user).await?;
+        Ok(true)
+    } else {
+        storage.delete_member(chat.id, user.id).await?;
+        Ok(false)
+    }
+}
+
+/// True if member of `kind` is in chat. Restricted members may be in chat or
+/// not, as their `is_member` flag says.
+fn is_in_chat(kind: &ChatMemberKind) -> bool {
     match kind {
         ChatMemberKind::Owner(_) | ChatMemberKind::Administrator(_) | ChatMemberKind::Member => {
-            storage.new_member(&chat, &user).await?;
-            return Ok(true);
+            true
         }
-        ChatMemberKind::Left | ChatMemberKind::Banned(_) => {
-            storage.delete_member(chat_id, user.id).await?;
-            return Ok(false);
+        ChatMemberKind::Restricted(restricted) => restricted.is_member,
+        ChatMemberKind::Left | ChatMemberKind::Banned(_) => false,
+    }
+}
+
+/// True for groups and supergroups, where members are tracked
+fn is_group(chat: &Chat) -> bool {
+    match chat.kind {
+        ChatKind::Public(ChatPublic {
+            kind: ref public_chat_kind,
+            ..
+        }) => !matches!(public_chat_kind, PublicChatKind::Channel(_)),
+        ChatKind::Private(_) => false,
+    }
+}
+
+/// Checks that bot is still in chat, so that its members can be checked
+async fn bot_in_chat(bot: &MyBot, chat_id: ChatId) -> Result<bool, RequestError> {
+    let me = bot.get_me().await?;
+    let member = bot.get_chat_member(chat_id, me.id).await?;
+    Ok(is_in_chat(&member.kind))
+}
+
+/// Periodically checks every tracked member, dropping those who left while we
+/// were not looking
+async fn revalidate_members(bot: MyBot, storage: Storage, config: RevalidateConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval.get()));
+    // sweep taking longer than interval is not followed by a burst of them
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let delay = Duration::from_millis(config.delay);
+    loop {
+        interval.tick().await;
+        let chats = match storage.tracked_chats().await {
+            Ok(chats) => chats,
+            Err(err) => {
+                error!("failed getting tracked chats: {}", err);
+                continue;
+            }
+        };
+        let mut checked = 0;
+        let mut gone = 0;
+        for chat_id in chats {
+            let chat = match bot.get_chat(chat_id).await {
+                Ok(chat) => chat,
+                Err(err) => {
+                    warn!("skipping chat {}, failed getting it: {}", chat_id, err);
+                    continue;
+                }
+            };
+            match bot_in_chat(&bot, chat_id).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("skipping chat {}, bot is not in it", chat_id);
+                    continue;
+                }
+                Err(err) => {
+                    warn!(
+                        "skipping chat {}, failed checking bot in it: {}",
+                        chat_id, err
+                    );
+                    continue;
+                }
+            }
+            let user_ids = match storage.chat_member_ids(chat_id).await {
+                Ok(user_ids) => user_ids,
+                Err(err) => {
+                    error!("failed getting members of {}: {}", chat_id, err);
+                    continue;
+                }
+            };
+            for user_id in user_ids {
+                match check_chat_member(&bot, &storage, &chat, user_id).await {
+                    Ok(true) => checked += 1,
+                    Ok(false) => {
+                        checked += 1;
+                        gone += 1;
+                    }
+                    Err(err) => warn!(
+                        "failed checking member chat_id: {} user_id: {}: {}",
+                        chat_id, user_id, err
+                    ),
+                }
+                tokio::time::sleep(delay).await;
+            }
         }
-        ChatMemberKind::Restricted(_) => {}
+        info!("revalidated {} members, {} not in chat", checked, gone);
     }
-    Ok(false)
 }
 
 #[tracing::instrument(skip_all, fields(msg_kind = %DisplayMessageKind::new(&msg.kind)))]
-async fn message_handler(bot: MyBot, msg: Message, storage: Storage) -> ResponseResult<()> {
+async fn message_handler(
+    bot: MyBot,
+    msg: Message,
+    storage: Storage,
+    tz: Timezone,
+    pending: PendingPings,
+) -> ResponseResult<()> {
+    if let Some(migration) = msg.chat_migration() {
+        let (from, to) = match *migration {
+            ChatMigration::To { chat_id } => (msg.chat.id, chat_id),
+            ChatMigration::From { chat_id } => (chat_id, msg.chat.id),
+        };
+        if let Err(err) = storage.migrate_chat(from, to).await {
+            error!("failed migrating chat: {}", err);
+        }
+        return Ok(());
+    }
+    if let (MessageKind::Common(_), Some(from)) = (&msg.kind, &msg.from) {
+        if let Err(err) = storage
+            .member_seen(&msg.chat, from, msg.date.timestamp())
+            .await
+        {
+            error!("failed recording member activity: {}", err);
+        }
+        if let Some(text) = msg.text() {
+            if !from.is_bot && !msg.chat.is_private() {
+                match storage.ping_triggers(msg.chat.id).await {
+                    Ok(triggers) => {
+                        if let Some(args) = strip_trigger(text, &triggers) {
+                            // phrase stands for everyone, the rest is text
+                            let (urgent, text) = parse_urgent(&args);
+                            let args =
+                                format!("{} {} {}", if urgent { "!" } else { "" }, ALL, text);
+                            ping_command(&bot, &storage, &pending, &msg, tz, &args).await;
+                        }
+                    }
+                    Err(err) => error!("failed getting ping triggers: {}", err),
+                }
+            }
+        }
+    }
     match msg.kind {
         MessageKind::NewChatMembers(members) => {
             for user in members.new_chat_members {
@@ -458,20 +1273,12 @@ async fn message_handler(bot: MyBot, msg: Message, storage: Storage) -> Response
                 if let Ok(ChatMember { user, kind }) =
                     bot.get_chat_member(msg.chat.id, user.id).await
                 {
-                    match kind {
-                        ChatMemberKind::Owner(_)
-                        | ChatMemberKind::Administrator(_)
-                        | ChatMemberKind::Member => {
-                            if let Err(err) = storage.new_member(&msg.chat, &user).await {
-                                error!("failed adding member: {}", err);
-                            }
+                    if is_in_chat(&kind) {
+                        if let Err(err) = storage.new_member(&msg.chat, &user).await {
+                            error!("failed adding member: {}", err);
                         }
-                        ChatMemberKind::Left | ChatMemberKind::Banned(_) => {
-                            if let Err(err) = storage.delete_member(msg.chat.id, user.id).await {
-                                error!("failed deleting member: {}", err);
-                            }
-                        }
-                        ChatMemberKind::Restricted(_) => {}
+                    } else if let Err(err) = storage.delete_member(msg.chat.id, user.id).await {
+                        error!("failed deleting member: {}", err);
                     }
                 }
             }
@@ -480,23 +1287,120 @@ async fn message_handler(bot: MyBot, msg: Message, storage: Storage) -> Response
     Ok(())
 }
 
-async fn reply(bot: &MyBot, chat_id: ChatId, msg_id: MessageId, text: &str) {
-    debug!("sending message: {}", text);
-    if let Err(err) = bot
-        .send_message(chat_id, text)
-        .reply_parameters(ReplyParameters {
-            message_id: msg_id,
-            ..Default::default()
-        })
-        .link_preview_options(LinkPreviewOptions {
-            is_disabled: true,
-            url: None,
-            prefer_small_media: true,
-            prefer_large_media: false,
-            show_above_text: false,
-        })
+#[tracing::instrument(skip_all, fields(chat_id = %upd.chat.id, user_id = %upd.new_chat_member.user.id))]
+async fn chat_member_handler(upd: ChatMemberUpdated, storage: Storage) -> ResponseResult<()> {
+    let ChatMember { ref user, ref kind } = upd.new_chat_member;
+    debug!(
+        "chat member updated: {:?} -> {:?}",
+        upd.old_chat_member.kind, kind
+    );
+    if is_in_chat(kind) {
+        if let Err(err) = storage.new_member(&upd.chat, user).await {
+            error!("failed adding member: {}", err);
+        }
+    } else if let Err(err) = storage.delete_member(upd.chat.id, user.id).await {
+        error!("failed deleting member: {}", err);
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(user_id = %q.from.id))]
+async fn callback_handler(
+    bot: MyBot,
+    q: CallbackQuery,
+    storage: Storage,
+    pending: PendingPings,
+) -> ResponseResult<()> {
+    debug!("callback query: {:?}", q.data);
+    if pending.answer(&bot, &storage, &q).await {
+        return Ok(());
+    }
+    if !ping::record_response(&bot, &storage, &q).await
+        && !roster::turn_page(&bot, &storage, &q).await
+    {
+        warn!("unknown callback query: {:?}", q.data);
+    }
+    if let Err(err) = bot.answer_callback_query(q.id).await {
+        warn!("failed answering callback query: {:?}", err);
+    }
+    Ok(())
+}
+
+/// Chat to send messages to, and forum topic in it
+#[derive(Debug, Clone, Copy)]
+struct Place {
+    chat_id: ChatId,
+    thread_id: Option<ThreadId>,
+}
+
+impl Place {
+    /// Where `msg` was sent
+    fn of(msg: &Message) -> Self {
+        Self {
+            chat_id: msg.chat.id,
+            thread_id: topic(msg),
+        }
+    }
+}
+
+impl From<ChatId> for Place {
+    fn from(chat_id: ChatId) -> Self {
+        Self {
+            chat_id,
+            thread_id: None,
+        }
+    }
+}
+
+/// Forum topic `msg` was sent to, if any
+fn topic(msg: &Message) -> Option<ThreadId> {
+    msg.thread_id.filter(|_| msg.is_topic_message)
+}
+
+async fn reply(bot: &MyBot, msg: &Message, text: &str) {
+    send(bot, Place::of(msg), Some(msg.id), text).await
+}
+
+/// Sends MarkdownV2 `text`
+async fn send(bot: &MyBot, place: Place, reply_to: Option<MessageId>, text: &str) {
+    if let Err(err) = message_request(bot, place, reply_to, text)
+        .parse_mode(ParseMode::MarkdownV2)
         .await
     {
         warn!("failed sending message: {:?}", err);
     }
 }
+
+/// Request sending plain `text` without link previews, for adding more
+/// options
+fn message_request(
+    bot: &MyBot,
+    place: Place,
+    reply_to: Option<MessageId>,
+    text: &str,
+) -> <MyBot as Requester>::SendMessage {
+    debug!("sending message: {}", text);
+    let mut request = bot
+        .send_message(place.chat_id, text)
+        .link_preview_options(no_link_preview());
+    if let Some(thread_id) = place.thread_id {
+        request = request.message_thread_id(thread_id);
+    }
+    if let Some(msg_id) = reply_to {
+        request = request.reply_parameters(ReplyParameters {
+            message_id: msg_id,
+            ..Default::default()
+        });
+    }
+    request
+}
+
+fn no_link_preview() -> LinkPreviewOptions {
+    LinkPreviewOptions {
+        is_disabled: true,
+        url: None,
+        prefer_small_media: true,
+        prefer_large_media: false,
+        show_above_text: false,
+    }
+}
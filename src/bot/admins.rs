@@ -0,0 +1,80 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use teloxide::{
+    prelude::*,
+    types::{ChatId, User, UserId},
+    RequestError,
+};
+use tracing::debug;
+
+use super::MyBot;
+
+/// How long fetched administrators are trusted
+const ADMINS_TTL: Duration = Duration::from_secs(5 * 60);
+/// How often one user may call administrators of chat
+const ADMIN_CALL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Owner and administrators of chats, fetched from Telegram on demand
+#[derive(Debug, Clone, Default)]
+pub(super) struct AdminCache(Arc<Mutex<HashMap<ChatId, Cached>>>);
+
+#[derive(Debug)]
+struct Cached {
+    fetched: Instant,
+    admins: Vec<User>,
+}
+
+impl AdminCache {
+    /// Administrators of chat, without bots
+    pub(super) async fn get(
+        &self,
+        bot: &MyBot,
+        chat_id: ChatId,
+    ) -> Result<Vec<User>, RequestError> {
+        if let Some(cached) = self.0.lock().unwrap().get(&chat_id) {
+            if cached.fetched.elapsed() < ADMINS_TTL {
+                return Ok(cached.admins.clone());
+            }
+        }
+        debug!("fetching administrators of {}", chat_id);
+        let admins: Vec<User> = bot
+            .get_chat_administrators(chat_id)
+            .await?
+            .into_iter()
+            .map(|member| member.user)
+            .filter(|user| !user.is_bot)
+            .collect();
+        self.0.lock().unwrap().insert(
+            chat_id,
+            Cached {
+                fetched: Instant::now(),
+                admins: admins.clone(),
+            },
+        );
+        Ok(admins)
+    }
+}
+
+/// When users last called administrators, by chat. Kept apart from cooldown
+/// and quota of chat so that spam can be reported whatever else is pinged,
+/// forgotten on restart.
+#[derive(Debug, Clone, Default)]
+pub(super) struct AdminCalls(Arc<Mutex<HashMap<(ChatId, UserId), Instant>>>);
+
+impl AdminCalls {
+    /// Remembers call of user, or returns how long to wait if user called
+    /// too recently
+    pub(super) fn call(&self, chat_id: ChatId, user_id: UserId) -> Result<(), Duration> {
+        let mut calls = self.0.lock().unwrap();
+        calls.retain(|_, called| called.elapsed() < ADMIN_CALL_INTERVAL);
+        if let Some(called) = calls.get(&(chat_id, user_id)) {
+            return Err(ADMIN_CALL_INTERVAL - called.elapsed());
+        }
+        calls.insert((chat_id, user_id), Instant::now());
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{FixedOffset, Utc};
+use teloxide::{
+    prelude::*,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, MessageId, User},
+};
+use tracing::{error, warn};
+
+use super::{
+    limits::{format_duration, limits_wait},
+    message_request,
+    ping::{ping_members, ping_targets, Call},
+    scheduler::save_scheduled_ping,
+    send, MyBot, Place,
+};
+use crate::storage::{Member, ScheduledPing, Storage};
+
+/// Prefix of callback data of confirmation buttons
+const CONFIRM_PREFIX: &str = "confirm:";
+/// Largest threshold chat may set
+pub(super) const MAX_CONFIRM_ABOVE: u32 = 100_000;
+/// How long ping waits for caller to confirm it
+const CONFIRM_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Pings waiting for their callers to confirm them, by confirmation message.
+/// Forgotten on restart, callers are asked to ping again then.
+#[derive(Clone, Default)]
+pub(super) struct PendingPings(Arc<Mutex<HashMap<(ChatId, MessageId), Pending>>>);
+
+struct Pending {
+    asked: Instant,
+    caller: User,
+    place: Place,
+    reply_to: MessageId,
+    action: Action,
+}
+
+/// What is done once caller confirms
+enum Action {
+    Ping {
+        text: String,
+        urgent: bool,
+        tz: FixedOffset,
+        limited: bool,
+        members: Vec<Member>,
+    },
+    Schedule {
+        ping: ScheduledPing,
+        tz: FixedOffset,
+    },
+}
+
+impl PendingPings {
+    /// Pings `members` right away, or asks caller to confirm first when they
+    /// are more than chat allows at once
+    pub(super) async fn ping(
+        &self,
+        bot: &MyBot,
+        storage: &Storage,
+        place: Place,
+        reply_to: MessageId,
+        call: Call<'_>,
+        members: Vec<Member>,
+    ) {
+        let size = ping_size(call.caller, &members);
+        if !needs_confirmation(storage, place.chat_id, size).await {
+            ping_members(bot, storage, place, Some(reply_to), call, members).await;
+            return;
+        }
+        let action = Action::Ping {
+            text: call.text.to_owned(),
+            urgent: call.urgent,
+            tz: call.tz,
+            limited: call.limited,
+            members,
+        };
+        self.ask(bot, call.caller, place, reply_to, size, action)
+            .await;
+    }
+
+    /// Saves scheduled ping right away, or asks `caller` to confirm first when
+    /// it is for more people than chat allows at once
+    #[allow(clippy::too_many_arguments)]
+    pub(super) async fn schedule(
+        &self,
+        bot: &MyBot,
+        storage: &Storage,
+        caller: Option<&User>,
+        place: Place,
+        reply_to: MessageId,
+        ping: ScheduledPing,
+        tz: FixedOffset,
+    ) {
+        let size = match ping_targets(storage, place, ping.group_name.as_deref()).await {
+            // scheduled pings have no caller to leave out
+            Ok(members) => ping_size(None, &members),
+            Err(err) => {
+                error!("failed getting members of {}: {}", place.chat_id, err);
+                return;
+            }
+        };
+        if !needs_confirmation(storage, place.chat_id, size).await {
+            save_scheduled_ping(bot, storage, place, reply_to, &ping, tz).await;
+            return;
+        }
+        self.ask(
+            bot,
+            caller,
+            place,
+            reply_to,
+            size,
+            Action::Schedule { ping, tz },
+        )
+        .await;
+    }
+
+    /// Asks caller whether to ping `size` people, keeping `action` until they
+    /// answer
+    async fn ask(
+        &self,
+        bot: &MyBot,
+        caller: Option<&User>,
+        place: Place,
+        reply_to: MessageId,
+        size: usize,
+        action: Action,
+    ) {
+        // anonymous admins all come as the same bot, who could not be told apart
+        let Some(caller) = caller.filter(|caller| !caller.is_bot) else {
+            send(
+                bot,
+                place,
+                Some(reply_to),
+                &format!(
+                    "Пинг `{}` человек нужно подтвердить, а анонимно это не получится, позови от своего имени",
+                    size
+                ),
+            )
+            .await;
+            return;
+        };
+        let keyboard = InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback("✅ Пингануть", format!("{}yes", CONFIRM_PREFIX)),
+            InlineKeyboardButton::callback("❌ Отмена", format!("{}no", CONFIRM_PREFIX)),
+        ]]);
+        let text = format!(
+            "Пингануть {} человек? Подтвердить может только {}",
+            size,
+            caller.full_name()
+        );
+        let message = match message_request(bot, place, Some(reply_to), &text)
+            .reply_markup(keyboard)
+            .await
+        {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("failed sending message: {:?}", err);
+                return;
+            }
+        };
+        let mut pending = self.0.lock().unwrap();
+        pending.retain(|_, ping| ping.asked.elapsed() < CONFIRM_TTL);
+        pending.insert(
+            (message.chat.id, message.id),
+            Pending {
+                asked: Instant::now(),
+                caller: caller.clone(),
+                place,
+                reply_to,
+                action,
+            },
+        );
+    }
+
+    /// Sends or cancels ping confirmation button was pressed for, answering
+    /// query itself, or tells caller to wait while they are over cooldown or
+    /// quota. Returns false if query is not about confirmation.
+    pub(super) async fn answer(&self, bot: &MyBot, storage: &Storage, q: &CallbackQuery) -> bool {
+        let Some(confirmed) = q
+            .data
+            .as_deref()
+            .and_then(|data| data.strip_prefix(CONFIRM_PREFIX))
+            .map(|answer| answer == "yes")
+        else {
+            return false;
+        };
+        let Some(ref message) = q.message else {
+            answer_query(bot, q, None).await;
+            return true;
+        };
+        let key = (message.chat().id, message.id());
+        let ping = {
+            let mut pending = self.0.lock().unwrap();
+            match pending.get(&key) {
+                Some(ping) if ping.caller.id != q.from.id => None,
+                _ => Some(pending.remove(&key)),
+            }
+        };
+        let Some(ping) = ping else {
+            answer_query(bot, q, Some("Отвечать может только позвавший")).await;
+            return true;
+        };
+        let Some(ping) = ping.filter(|ping| ping.asked.elapsed() < CONFIRM_TTL) else {
+            answer_query(bot, q, None).await;
+            edit(bot, key, "Пинг устарел, позови ещё раз").await;
+            return true;
+        };
+        if !confirmed {
+            answer_query(bot, q, None).await;
+            edit(bot, key, "Пинг отменён").await;
+            return true;
+        }
+        // limits are checked again, several confirmations could be waiting
+        let limited = !matches!(ping.action, Action::Ping { limited: false, .. });
+        let (chat_id, now) = (ping.place.chat_id, Utc::now().timestamp());
+        let wait = if limited {
+            limits_wait(storage, chat_id, ping.caller.id, now).await
+        } else {
+            None
+        };
+        if let Some(wait) = wait {
+            let alert = format!(
+                "Слишком часто, следующий пинг можно через {}",
+                format_duration(wait)
+            );
+            answer_query(bot, q, Some(&alert)).await;
+            // may be confirmed again once limits allow, while still fresh
+            self.0.lock().unwrap().insert(key, ping);
+            return true;
+        }
+        answer_query(bot, q, None).await;
+        if let Err(err) = bot.delete_message(key.0, key.1).await {
+            warn!("failed deleting confirmation: {:?}", err);
+        }
+        match ping.action {
+            Action::Ping {
+                text,
+                urgent,
+                tz,
+                limited,
+                members,
+            } => {
+                let call = Call {
+                    caller: Some(&ping.caller),
+                    text: &text,
+                    urgent,
+                    tz,
+                    limited,
+                };
+                ping_members(bot, storage, ping.place, Some(ping.reply_to), call, members).await;
+            }
+            Action::Schedule {
+                ping: scheduled,
+                tz,
+            } => {
+                save_scheduled_ping(bot, storage, ping.place, ping.reply_to, &scheduled, tz).await;
+            }
+        }
+        true
+    }
+}
+
+/// How many of `members` ping would notify, not counting bots and caller
+fn ping_size(caller: Option<&User>, members: &[Member]) -> usize {
+    members
+        .iter()
+        .filter(|member| !member.is_bot)
+        .filter(|member| caller.is_none_or(|f| f.id.0.to_string() != member.user_id))
+        .count()
+}
+
+/// True if chat wants pings of `size` people confirmed
+async fn needs_confirmation(storage: &Storage, chat_id: ChatId, size: usize) -> bool {
+    match storage.chat_settings(chat_id).await {
+        Ok(settings) => settings.confirm_above > 0 && size as i64 > settings.confirm_above,
+        Err(err) => {
+            error!("failed getting chat settings: {}", err);
+            false
+        }
+    }
+}
+
+async fn answer_query(bot: &MyBot, q: &CallbackQuery, alert: Option<&str>) {
+    let mut request = bot.answer_callback_query(q.id.clone());
+    if let Some(alert) = alert {
+        request = request.text(alert).show_alert(true);
+    }
+    if let Err(err) = request.await {
+        warn!("failed answering callback query: {:?}", err);
+    }
+}
+
+/// Replaces confirmation with `text`, dropping its buttons
+async fn edit(bot: &MyBot, (chat_id, message_id): (ChatId, MessageId), text: &str) {
+    if let Err(err) = bot.edit_message_text(chat_id, message_id, text).await {
+        warn!("failed editing confirmation: {:?}", err);
+    }
+}
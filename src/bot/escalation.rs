@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use teloxide::types::{ChatId, Message, MessageEntityKind, MessageId, ThreadId, UserId};
+use tracing::{debug, error};
+
+use super::{
+    ping::{chat_user_settings, member_settings, mention_messages, mentions_cap, send_rendered},
+    private::{reminder_dm, send_dms},
+    render::Rendered,
+    MyBot, Place,
+};
+use crate::storage::{Member, SentPing, Storage};
+
+/// How often due escalations are looked for
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Shortest interval between repeated mentions
+pub(super) const MIN_ESCALATION_INTERVAL: i64 = 60;
+/// Longest interval between repeated mentions, pings are forgotten in a week
+pub(super) const MAX_ESCALATION_INTERVAL: i64 = 24 * 60 * 60;
+pub(super) const MAX_ESCALATION_ROUNDS: i64 = 5;
+
+/// Mentions again those who did not respond to pings, forever. `tz` is
+/// timezone of users who did not set theirs, for quiet hours.
+pub(super) async fn run_escalations(bot: MyBot, storage: Storage, tz: FixedOffset) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let due = match storage.due_escalations(now.timestamp()).await {
+            Ok(due) => due,
+            Err(err) => {
+                error!("failed getting due escalations: {}", err);
+                continue;
+            }
+        };
+        for ping in due {
+            if let Err(err) = escalate(&bot, &storage, &ping, now, tz).await {
+                error!("failed escalating ping: {}", err);
+            }
+        }
+    }
+}
+
+/// Mentions targets of `ping` who did not respond once more, or sends private
+/// messages to those getting pings that way, mentioning them if that fails.
+/// Those having quiet hours are left alone. When no rounds are left, reports
+/// those still silent to whoever pinged privately, or in chat if that fails.
+async fn escalate(
+    bot: &MyBot,
+    storage: &Storage,
+    ping: &SentPing,
+    now: DateTime<Utc>,
+    tz: FixedOffset,
+) -> Result<(), sqlx::Error> {
+    let (chat_id, message_id) = (ChatId(ping.chat_id), MessageId(ping.message_id));
+    let place = Place {
+        chat_id,
+        thread_id: ping
+            .thread_id
+            .map(|thread_id| ThreadId(MessageId(thread_id))),
+    };
+    let silent = storage.silent_targets(chat_id, message_id).await?;
+    debug!(
+        "escalating ping chat_id: {} message_id: {} rounds_left: {} silent: {}",
+        chat_id,
+        message_id,
+        ping.rounds_left,
+        silent.len()
+    );
+    let settings = storage.chat_settings(chat_id).await?;
+    let interval = settings.escalation_interval;
+    // everyone responded, or escalation was turned off since
+    if silent.is_empty() || interval == 0 {
+        return storage
+            .update_escalation(chat_id, message_id, ping.next_round, 0, None)
+            .await
+            .map(|_| ());
+    }
+
+    let (rounds_left, next_round) = if ping.rounds_left > 0 {
+        (ping.rounds_left - 1, Some(now.timestamp() + interval))
+    } else {
+        (0, None)
+    };
+    // updated before sending, so that failures don't make us mention twice,
+    // and only the instance that updated it sends
+    if !storage
+        .update_escalation(
+            chat_id,
+            message_id,
+            ping.next_round,
+            rounds_left,
+            next_round,
+        )
+        .await?
+    {
+        debug!("escalation was taken by another instance");
+        return Ok(());
+    }
+    if ping.rounds_left > 0 {
+        // reminded the way they get pings, unless they have quiet hours now
+        let user_settings = chat_user_settings(storage, chat_id).await;
+        let settings_of = |member: &Member| member_settings(&user_settings, member);
+        let awake: Vec<&Member> = silent
+            .iter()
+            .filter(|member| !settings_of(member).is_quiet(now, tz))
+            .collect();
+        let in_dm: Vec<&Member> = awake
+            .iter()
+            .copied()
+            .filter(|member| settings_of(member).delivery.in_dm())
+            .collect();
+        let failed = send_dms(bot, &reminder_dm(chat_id, message_id), &in_dm).await;
+        // those private message did not reach are mentioned instead
+        let in_group: Vec<Member> = awake
+            .iter()
+            .copied()
+            .filter(|member| {
+                settings_of(member).delivery.in_group()
+                    || failed.iter().any(|failed| failed.user_id == member.user_id)
+            })
+            .cloned()
+            .collect();
+        let header = Rendered::from("Ещё не ответили на пинг:\n\n");
+        for body in mention_messages(&header, &in_group, mentions_cap(&settings)) {
+            send_rendered(bot, place, Some(message_id), &header, body, None).await;
+        }
+    } else {
+        let names = silent
+            .iter()
+            .map(Member::full_name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let caller = ping.created_by.as_deref().and_then(|id| id.parse().ok());
+        if let Some(caller) = caller.map(UserId) {
+            let mut text = Rendered::from("Так и не ответили на твой пинг: ");
+            text.push(&names);
+            if let Some(url) = Message::url_of(chat_id, None, message_id) {
+                text.push("\n\n")
+                    .push_entity("К сообщению", MessageEntityKind::TextLink { url });
+            }
+            let dm = Place::from(ChatId::from(caller));
+            if send_rendered(bot, dm, None, &Rendered::default(), text, None)
+                .await
+                .is_some()
+            {
+                return Ok(());
+            }
+        }
+        // caller did not start private chat with bot, or there is no caller
+        let mut text = Rendered::default();
+        match caller {
+            Some(user_id) => {
+                text.push_user_link("Пинговавший", UserId(user_id))
+                    .push(", так и не ответили: ");
+            }
+            None => {
+                text.push("Так и не ответили: ");
+            }
+        }
+        text.push(&names);
+        send_rendered(
+            bot,
+            place,
+            Some(message_id),
+            &Rendered::default(),
+            text,
+            None,
+        )
+        .await;
+    }
+    Ok(())
+}
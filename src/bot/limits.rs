@@ -0,0 +1,210 @@
+use teloxide::types::{ChatId, Message, UserId};
+use tracing::error;
+
+use super::{is_chat_admin, reply, MyBot};
+use crate::storage::{PingPolicy, Storage};
+
+const DAY: i64 = 24 * 60 * 60;
+/// Longest cooldown, pings older than a day are not remembered
+pub(super) const MAX_COOLDOWN: i64 = DAY;
+
+/// Seconds left until `user_id` may ping chat again at `now` (unix time),
+/// `None` if allowed right away
+async fn ping_wait(
+    storage: &Storage,
+    chat_id: ChatId,
+    user_id: UserId,
+    now: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    let settings = storage.chat_settings(chat_id).await?;
+    let mut wait = 0;
+    if settings.ping_cooldown > 0 {
+        if let Some(last) = storage.last_ping(chat_id).await? {
+            wait = wait.max(last + settings.ping_cooldown - now);
+        }
+    }
+    if settings.daily_quota > 0 {
+        let pings = storage.user_pings(chat_id, user_id, now - DAY).await?;
+        let quota = settings.daily_quota as usize;
+        if pings.len() >= quota {
+            // the oldest ping that has to expire to get under quota
+            let oldest = pings[pings.len() - quota];
+            wait = wait.max(oldest + DAY - now);
+        }
+    }
+    Ok((wait > 0).then_some(wait))
+}
+
+/// Checks ping policy of chat for sender of `msg`, replying with refusal if
+/// sender may not ping
+async fn may_ping(bot: &MyBot, storage: &Storage, msg: &Message) -> bool {
+    let Some(ref from) = msg.from else {
+        return false;
+    };
+    let policy = match storage.chat_settings(msg.chat.id).await {
+        Ok(settings) => settings.ping_policy,
+        Err(err) => {
+            error!("failed getting chat settings: {}", err);
+            PingPolicy::default()
+        }
+    };
+    let refusal = match policy {
+        PingPolicy::All => return true,
+        PingPolicy::Admins => "В этом чате пинговать могут только админы",
+        PingPolicy::Allowlist => {
+            match storage.is_ping_allowed(msg.chat.id, from.id).await {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(err) => error!("failed checking ping allowlist: {}", err),
+            }
+            "В этом чате пинговать могут только админы и те, кому они разрешили"
+        }
+    };
+    if is_chat_admin(bot, msg.chat.id, from.id).await {
+        return true;
+    }
+    reply(bot, msg, refusal).await;
+    false
+}
+
+/// Checks ping policy, cooldown and quota of chat for sender of `msg`,
+/// replying with refusal or time to wait if ping is not allowed. Pings are
+/// recorded once sent, see [`record_ping`].
+pub(super) async fn ping_allowed(bot: &MyBot, storage: &Storage, msg: &Message) -> bool {
+    let Some(ref from) = msg.from else {
+        return false;
+    };
+    if !may_ping(bot, storage, msg).await {
+        return false;
+    }
+    let now = msg.date.timestamp();
+    match ping_wait(storage, msg.chat.id, from.id, now).await {
+        Ok(Some(wait)) => {
+            reply(
+                bot,
+                msg,
+                &format!(
+                    "Слишком часто, следующий пинг можно через `{}`",
+                    format_duration(wait)
+                ),
+            )
+            .await;
+            return false;
+        }
+        Ok(None) => {}
+        Err(err) => error!("failed checking ping limits: {}", err),
+    }
+    true
+}
+
+/// Checks cooldown and quota of chat for `user_id` pinging without message,
+/// like scheduled pings do
+pub(super) async fn within_limits(
+    storage: &Storage,
+    chat_id: ChatId,
+    user_id: UserId,
+    now: i64,
+) -> bool {
+    limits_wait(storage, chat_id, user_id, now).await.is_none()
+}
+
+/// Same as [`within_limits`], returns seconds left until `user_id` may ping,
+/// `None` if allowed right away
+pub(super) async fn limits_wait(
+    storage: &Storage,
+    chat_id: ChatId,
+    user_id: UserId,
+    now: i64,
+) -> Option<i64> {
+    ping_wait(storage, chat_id, user_id, now)
+        .await
+        .unwrap_or_else(|err| {
+            error!("failed checking ping limits: {}", err);
+            None
+        })
+}
+
+/// Records ping sent by `user_id` at `sent_at` (unix time), for cooldown and
+/// quota
+pub(super) async fn record_ping(storage: &Storage, chat_id: ChatId, user_id: UserId, sent_at: i64) {
+    if let Err(err) = storage.log_ping(chat_id, user_id, sent_at).await {
+        error!("failed logging ping: {}", err);
+    }
+}
+
+/// Formats seconds like `1 ч 5 мин` or `30 сек`
+pub(super) fn format_duration(secs: i64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    let parts: Vec<String> = [(hours, "ч"), (minutes, "мин"), (seconds, "сек")]
+        .into_iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{} {}", value, unit))
+        .collect();
+    if parts.is_empty() {
+        "0 сек".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Parses duration like `90`, `30s`, `5m` or `1h` into seconds
+pub(super) fn parse_duration(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return None,
+    };
+    value.parse::<i64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::StorageConfig, storage::ChatSettings};
+
+    const CHAT: ChatId = ChatId(-1001);
+
+    #[tokio::test]
+    async fn cooldown_and_quota() {
+        let storage = Storage::init(&StorageConfig::Memory).await.unwrap();
+        let (alice, bob) = (UserId(1), UserId(2));
+        let now = 1_000_000;
+        assert!(within_limits(&storage, CHAT, alice, now).await);
+
+        let settings = ChatSettings {
+            ping_cooldown: 60,
+            daily_quota: 2,
+            ..Default::default()
+        };
+        storage.save_chat_settings(CHAT, &settings).await.unwrap();
+        record_ping(&storage, CHAT, alice, now).await;
+        // cooldown is for everyone in chat
+        assert!(!within_limits(&storage, CHAT, bob, now + 59).await);
+        assert!(within_limits(&storage, CHAT, bob, now + 60).await);
+        record_ping(&storage, CHAT, alice, now + 60).await;
+        // quota is per user, until the oldest ping is a day old
+        assert!(!within_limits(&storage, CHAT, alice, now + 120).await);
+        assert!(within_limits(&storage, CHAT, bob, now + 120).await);
+        assert_eq!(
+            ping_wait(&storage, CHAT, alice, now + 120).await.unwrap(),
+            Some(DAY - 120)
+        );
+        assert!(within_limits(&storage, CHAT, alice, now + DAY).await);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("5m"), Some(300));
+        assert_eq!(parse_duration("1h"), Some(3600));
+        assert_eq!(parse_duration("1d"), None);
+        assert_eq!(format_duration(3930), "1 ч 5 мин 30 сек");
+        assert_eq!(format_duration(0), "0 сек");
+    }
+}
@@ -0,0 +1,817 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+use chrono::{FixedOffset, Utc};
+use teloxide::{
+    prelude::*,
+    types::{
+        CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode, User,
+        UserId,
+    },
+    utils::markdown,
+    ApiError, RequestError,
+};
+use tracing::{debug, error, warn};
+
+use super::{
+    limits::record_ping,
+    message_request, no_link_preview,
+    private::{ping_dm, send_dms},
+    render::{Rendered, MAX_MESSAGE_LEN},
+    send, split_word, MyBot, Place,
+};
+use crate::storage::{
+    ChatSettings, Member, PingResponse, Responder, SentPing, Storage, UserSettings,
+};
+
+/// Prefix of callback data of acknowledgement buttons
+const ACK_PREFIX: &str = "ping:";
+/// Mentions per message unless chat sets otherwise
+const MENTIONS_PER_MESSAGE: usize = 40;
+/// Room left in first message of ping for names of those who respond
+const RESPONSES_ROOM: usize = 512;
+/// Longest first message of ping, see [`RESPONSES_ROOM`]
+const MAX_FIRST_LEN: usize = MAX_MESSAGE_LEN - RESPONSES_ROOM;
+
+/// Word pinging everyone in place of group name, so that text after it is not
+/// taken for one
+pub(super) const ALL: &str = "all";
+
+/// Why ping arguments were refused
+#[derive(Debug)]
+pub(super) enum PingArgsError {
+    /// First word is neither group name nor [`ALL`]
+    BadName,
+    /// No group with such name, or nobody in it
+    EmptyGroup(String),
+    /// Groups could not be read, error is logged
+    Storage,
+}
+
+impl fmt::Display for PingArgsError {
+    /// Formats as MarkdownV2 reply to caller
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadName => write!(
+                f,
+                "Неправильное имя группы, пингануть всех с текстом: `/ping {} текст`",
+                ALL
+            ),
+            Self::EmptyGroup(group) => write!(
+                f,
+                "В группе `{}` никого нет, пингануть всех с текстом: `/ping {} текст`",
+                markdown::escape_code(group),
+                ALL
+            ),
+            Self::Storage => write!(f, "Не получилось"),
+        }
+    }
+}
+
+/// Splits ping arguments into group name and the rest of text. Arguments
+/// either are empty, or start with [`ALL`] or name of group having members,
+/// anything else is refused rather than pinging the whole chat by mistake.
+pub(super) async fn parse_ping_args<'a>(
+    storage: &Storage,
+    chat_id: ChatId,
+    args: &'a str,
+) -> Result<(Option<String>, &'a str), PingArgsError> {
+    let (first, rest) = split_word(args);
+    if first.is_empty() {
+        return Ok((None, ""));
+    }
+    if first.eq_ignore_ascii_case(ALL) {
+        return Ok((None, rest));
+    }
+    let group = group_name(first).ok_or(PingArgsError::BadName)?;
+    let groups = storage.chat_groups(chat_id).await.map_err(|err| {
+        error!("failed getting groups of {}: {}", chat_id, err);
+        PingArgsError::Storage
+    })?;
+    if !groups
+        .iter()
+        .any(|(name, count)| *name == group && *count > 0)
+    {
+        return Err(PingArgsError::EmptyGroup(group));
+    }
+    Ok((Some(group), rest))
+}
+
+/// Strips `!` marking urgent ping, one that wakes those having quiet hours
+pub(super) fn parse_urgent(args: &str) -> (bool, &str) {
+    match split_word(args) {
+        ("!", rest) => (true, rest),
+        _ => (false, args.trim()),
+    }
+}
+
+/// Members of `group`, or followers of forum topic when it has any, or of the
+/// whole chat
+pub(super) async fn ping_targets(
+    storage: &Storage,
+    place: Place,
+    group: Option<&str>,
+) -> Result<Vec<Member>, sqlx::Error> {
+    if let Some(group) = group {
+        return storage.group_members(place.chat_id, group).await;
+    }
+    if let Some(thread_id) = place.thread_id {
+        let followers = storage.topic_followers(place.chat_id, thread_id).await?;
+        if !followers.is_empty() {
+            return Ok(followers);
+        }
+    }
+    storage.chat_members(place.chat_id).await
+}
+
+/// Who pings and what about
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Call<'a> {
+    pub(super) caller: Option<&'a User>,
+    pub(super) text: &'a str,
+    /// Notify those having quiet hours too
+    pub(super) urgent: bool,
+    /// Timezone of users who did not set theirs
+    pub(super) tz: FixedOffset,
+    /// Counts for cooldown and quota of chat, calls of administrators don't
+    pub(super) limited: bool,
+}
+
+/// Members ping is for, with their preferences
+pub(super) struct Targets {
+    /// Those to notify
+    pub(super) members: Vec<Member>,
+    /// Those having quiet hours, only named
+    pub(super) quiet: Vec<Member>,
+    user_settings: HashMap<String, UserSettings>,
+}
+
+impl Targets {
+    /// Sorts out `members` by whether `call` notifies them, leaving out bots
+    /// and caller
+    pub(super) async fn new(
+        storage: &Storage,
+        chat_id: ChatId,
+        call: &Call<'_>,
+        members: Vec<Member>,
+    ) -> Self {
+        let members = members
+            .into_iter()
+            .filter(|member| !member.is_bot)
+            .filter(|member| {
+                call.caller
+                    .is_none_or(|f| f.id.0.to_string() != member.user_id)
+            });
+        let user_settings = chat_user_settings(storage, chat_id).await;
+        let now = Utc::now();
+        let (quiet, members) = members.partition(|member| {
+            !call.urgent
+                && user_settings
+                    .get(&member.user_id)
+                    .is_some_and(|settings| settings.is_quiet(now, call.tz))
+        });
+        Self {
+            members,
+            quiet,
+            user_settings,
+        }
+    }
+
+    fn settings(&self, member: &Member) -> UserSettings {
+        member_settings(&self.user_settings, member)
+    }
+
+    /// Those to mention in chat
+    pub(super) fn in_group(&self) -> Vec<Member> {
+        self.members
+            .iter()
+            .filter(|member| self.settings(member).delivery.in_group())
+            .cloned()
+            .collect()
+    }
+
+    /// Those to send private messages
+    pub(super) fn in_dm(&self) -> Vec<&Member> {
+        self.members
+            .iter()
+            .filter(|member| self.settings(member).delivery.in_dm())
+            .collect()
+    }
+}
+
+/// Settings of chat members who changed any, by user id
+pub(super) async fn chat_user_settings(
+    storage: &Storage,
+    chat_id: ChatId,
+) -> HashMap<String, UserSettings> {
+    match storage.chat_user_settings(chat_id).await {
+        Ok(user_settings) => user_settings
+            .into_iter()
+            .map(|(user_id, settings)| (user_id.to_string(), settings))
+            .collect(),
+        Err(err) => {
+            error!("failed getting user settings: {}", err);
+            HashMap::new()
+        }
+    }
+}
+
+/// Settings of `member` from [`chat_user_settings`], defaults if never changed
+pub(super) fn member_settings(
+    user_settings: &HashMap<String, UserSettings>,
+    member: &Member,
+) -> UserSettings {
+    user_settings
+        .get(&member.user_id)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Start of every ping message, naming caller and quoting text
+pub(super) fn header(call: &Call<'_>) -> Rendered {
+    let mut header = Rendered::default();
+    if let Some(u) = call.caller {
+        header.push_mention(u).push(" вызывает!\n\n");
+    }
+    if !call.text.is_empty() {
+        header.push(call.text).push("\n\n");
+    }
+    header
+}
+
+/// Mentions `members` at `place` in replies to `reply_to_msg_id`, as many
+/// mentions per message as chat allows, every message starts with header.
+/// First message gets acknowledgement buttons. Members preferring private
+/// messages get those instead of or along with mentions, and are mentioned
+/// when private message fails. Members having quiet hours are only named,
+/// unless call is urgent. Returns true if ping was sent, then it counts for
+/// cooldown and quota of caller if call is limited.
+pub(super) async fn ping_members(
+    bot: &MyBot,
+    storage: &Storage,
+    place: Place,
+    reply_to_msg_id: Option<MessageId>,
+    call: Call<'_>,
+    members: Vec<Member>,
+) -> bool {
+    let Call { caller, text, .. } = call;
+    let chat_id = place.chat_id;
+    let header = header(&call);
+    let targets = Targets::new(storage, chat_id, &call, members).await;
+    let (in_group, in_dm, quiet) = (targets.in_group(), targets.in_dm(), &targets.quiet);
+
+    let chat_settings = match storage.chat_settings(chat_id).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            error!("failed getting chat settings: {}", err);
+            Default::default()
+        }
+    };
+    let cap = mentions_cap(&chat_settings);
+    let mut messages = mention_messages(&header, &in_group, cap).into_iter();
+    let mut first = match messages.next() {
+        Some(first) => first,
+        None if !in_dm.is_empty() => Rendered::from("Позвал всех в личке"),
+        None if !quiet.is_empty() => Rendered::default(),
+        None => {
+            send(bot, place, reply_to_msg_id, "Тут нет никого, кроме нас").await;
+            return false;
+        }
+    };
+    // sent separately when it does not fit into first message
+    let mut quiet_line = None;
+    if !quiet.is_empty() {
+        let names: Vec<String> = quiet.iter().map(Member::full_name).collect();
+        let line =
+            Rendered::from(format!("Не беспокою, у них тихие часы: {}", names.join(", ")).as_str());
+        let mut appended = Rendered::from(if first.is_empty() { "" } else { "\n\n" });
+        appended.append(&line);
+        if first.is_empty() || Rendered::fit_in(&[&header, &first, &appended], MAX_FIRST_LEN) {
+            first.append(&appended);
+        } else {
+            quiet_line = Some(line);
+        }
+    }
+
+    let msg = send_rendered(
+        bot,
+        place,
+        reply_to_msg_id,
+        &header,
+        first,
+        Some(ack_keyboard()),
+    )
+    .await;
+    for body in messages {
+        send_rendered(bot, place, reply_to_msg_id, &header, body, None).await;
+    }
+    if let Some(line) = quiet_line {
+        send_rendered(
+            bot,
+            place,
+            reply_to_msg_id,
+            &Rendered::default(),
+            line,
+            None,
+        )
+        .await;
+    }
+    let failed = send_dms(bot, &ping_dm(msg.as_ref(), caller, text), &in_dm).await;
+    // mentioned instead, unless they already are
+    let fallback: Vec<Member> = failed
+        .into_iter()
+        .filter(|member| !targets.settings(member).delivery.in_group())
+        .cloned()
+        .collect();
+    for body in mention_messages(&header, &fallback, cap) {
+        send_rendered(bot, place, reply_to_msg_id, &header, body, None).await;
+    }
+    let Some(msg) = msg else {
+        return false;
+    };
+
+    let sent_at = msg.date.timestamp();
+    if let Some(caller) = caller.filter(|_| call.limited) {
+        record_ping(storage, chat_id, caller.id, sent_at).await;
+    }
+    let escalate = chat_settings.escalation_interval > 0;
+    let ping = SentPing {
+        chat_id: chat_id.0,
+        message_id: msg.id.0,
+        created_by: caller.map(|u| u.id.to_string()),
+        text: msg.text().unwrap_or_default().to_owned(),
+        entities: serde_json::to_string(msg.entities().unwrap_or_default()).ok(),
+        sent_at,
+        rounds_left: if escalate {
+            chat_settings.escalation_rounds
+        } else {
+            0
+        },
+        next_round: escalate.then(|| sent_at + chat_settings.escalation_interval),
+        thread_id: place.thread_id.map(|thread_id| thread_id.0 .0),
+    };
+    let user_ids: Vec<UserId> = targets
+        .members
+        .iter()
+        .filter_map(|member| member.user_id.parse().ok().map(UserId))
+        .collect();
+    if let Err(err) = storage.add_sent_ping(&ping, &user_ids).await {
+        error!("failed saving sent ping: {}", err);
+    }
+    true
+}
+
+/// Most mentions per message chat allows
+pub(super) fn mentions_cap(settings: &ChatSettings) -> usize {
+    match settings.mentions_per_message {
+        0 => MENTIONS_PER_MESSAGE,
+        cap => cap as usize,
+    }
+}
+
+/// Bodies of messages mentioning `members`, each to follow `header`, with no
+/// more than `cap` mentions and within Telegram limits of length and entities
+/// along with header. First one leaves room for names of those who respond.
+pub(super) fn mention_messages(header: &Rendered, members: &[Member], cap: usize) -> Vec<Rendered> {
+    let mut messages = Vec::new();
+    let mut buf = Rendered::default();
+    let mut count = 0;
+    for member in members {
+        let mut mention = Rendered::from(" ");
+        mention.push_member(member);
+        let len = if messages.is_empty() {
+            MAX_FIRST_LEN
+        } else {
+            MAX_MESSAGE_LEN
+        };
+        if count > 0 && (count >= cap || !Rendered::fit_in(&[header, &buf, &mention], len)) {
+            messages.push(std::mem::take(&mut buf));
+            count = 0;
+        }
+        buf.append(&mention);
+        count += 1;
+    }
+    if count > 0 {
+        messages.push(buf);
+    }
+    messages
+}
+
+/// Request sending `text` with its entities, for adding more options
+fn rendered_request(
+    bot: &MyBot,
+    place: Place,
+    reply_to: Option<MessageId>,
+    text: &Rendered,
+) -> <MyBot as Requester>::SendMessage {
+    message_request(bot, place, reply_to, &text.text).entities(text.entities.clone())
+}
+
+/// Sends `header` followed by `body`. For as long as Telegram finds message
+/// too long, body is split in parts each following header, and when body
+/// can't be split any more, header is shortened. Returns first sent message,
+/// the one getting `markup`.
+pub(super) async fn send_rendered(
+    bot: &MyBot,
+    place: Place,
+    reply_to: Option<MessageId>,
+    header: &Rendered,
+    body: Rendered,
+    markup: Option<InlineKeyboardMarkup>,
+) -> Option<Message> {
+    // parts left to send, last one goes first
+    let mut parts = vec![(header.clone(), body)];
+    let mut first = None;
+    while let Some((mut header, body)) = parts.pop() {
+        let mut text = header.clone();
+        text.append(&body);
+        let mut request = rendered_request(bot, place, reply_to, &text);
+        if let (None, Some(markup)) = (&first, &markup) {
+            request = request.reply_markup(markup.clone());
+        }
+        match request.await {
+            Ok(msg) => {
+                first.get_or_insert(msg);
+            }
+            Err(RequestError::Api(ApiError::MessageIsTooLong)) => {
+                if let Some((head, tail)) = body.split_in_half() {
+                    debug!("splitting too long message of {} characters", text.len());
+                    parts.push((header.clone(), tail));
+                    parts.push((header, head));
+                } else if !header.is_empty() {
+                    debug!(
+                        "shortening header of too long message of {} characters",
+                        text.len()
+                    );
+                    let len = match MAX_MESSAGE_LEN.checked_sub(body.len()) {
+                        Some(len) if len < header.len() => len,
+                        // Telegram counts differently, or body alone is too long
+                        _ => header.len() / 2,
+                    };
+                    header.truncate(len);
+                    parts.push((header, body));
+                } else {
+                    warn!("message of {} characters is too long", text.len());
+                }
+            }
+            Err(err) => warn!("failed sending message: {:?}", err),
+        }
+    }
+    first
+}
+
+fn ack_keyboard() -> InlineKeyboardMarkup {
+    let button = |text: &str, response: PingResponse| {
+        InlineKeyboardButton::callback(text, format!("{}{}", ACK_PREFIX, response.as_str()))
+    };
+    InlineKeyboardMarkup::new([[
+        button("✅ Я тут", PingResponse::Here),
+        button("❌ Не смогу", PingResponse::Away),
+    ]])
+}
+
+/// Records response given with acknowledgement button and updates ping
+/// message with everyone who responded. Returns false if query is not about
+/// ping.
+pub(super) async fn record_response(bot: &MyBot, storage: &Storage, q: &CallbackQuery) -> bool {
+    let Some(response) = q
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(ACK_PREFIX))
+        .and_then(|response| response.parse::<PingResponse>().ok())
+    else {
+        return false;
+    };
+    let Some(ref message) = q.message else {
+        return true;
+    };
+    let (chat, message_id) = (message.chat(), message.id());
+    if let Err(err) = storage.new_member(chat, &q.from).await {
+        error!("failed adding member: {}", err);
+    }
+    let now = Utc::now().timestamp();
+    match storage
+        .set_ping_response(chat.id, message_id, q.from.id, response, now)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return true,
+        Err(err) => {
+            error!("failed saving ping response: {}", err);
+            return true;
+        }
+    }
+    let (ping, responses) = match (
+        storage.sent_ping(chat.id, message_id).await,
+        storage.ping_responses(chat.id, message_id).await,
+    ) {
+        (Ok(Some(ping)), Ok(responses)) => (ping, responses),
+        (Ok(None), _) => return true,
+        (Err(err), _) | (_, Err(err)) => {
+            error!("failed getting ping responses: {}", err);
+            return true;
+        }
+    };
+    let request = match ping.entities {
+        Some(ref entities) => {
+            let text = Rendered {
+                text: ping.text.clone(),
+                entities: serde_json::from_str(entities).unwrap_or_default(),
+            };
+            let text = with_responses(text, &responses);
+            bot.edit_message_text(chat.id, message_id, text.text)
+                .entities(text.entities)
+        }
+        None => bot
+            .edit_message_text(
+                chat.id,
+                message_id,
+                format!(
+                    "{}{}",
+                    ping.text,
+                    markdown::escape(&response_lines(&responses))
+                ),
+            )
+            .parse_mode(ParseMode::MarkdownV2),
+    };
+    if let Err(err) = request
+        .link_preview_options(no_link_preview())
+        .reply_markup(ack_keyboard())
+        .await
+    {
+        warn!("failed editing ping message: {:?}", err);
+    }
+    true
+}
+
+/// Ping message `text` followed by names of those who responded, shortened
+/// to fit when there are more than [`RESPONSES_ROOM`] takes
+fn with_responses(mut text: Rendered, responses: &[Responder]) -> Rendered {
+    let mut lines = Rendered::from(response_lines(responses).as_str());
+    lines.truncate(MAX_MESSAGE_LEN.saturating_sub(text.len()));
+    text.append(&lines);
+    text
+}
+
+/// Names of those who responded, to follow ping message text
+fn response_lines(responses: &[Responder]) -> String {
+    let mut buf = String::new();
+    for (response, label) in [
+        (PingResponse::Here, "✅ Тут"),
+        (PingResponse::Away, "❌ Не смогут"),
+    ] {
+        let names: Vec<String> = responses
+            .iter()
+            .filter(|responder| responder.response == response)
+            .map(|responder| responder.member.full_name())
+            .collect();
+        if !names.is_empty() {
+            let _ = write!(buf, "\n\n{}: {}", label, names.join(", "));
+        }
+    }
+    buf
+}
+
+/// Normalizes group name, returns None if name is not valid or is [`ALL`]
+pub(super) fn group_name(name: &str) -> Option<String> {
+    let name = name.trim().trim_start_matches('#').to_lowercase();
+    if name.is_empty()
+        || name == ALL
+        || name.chars().count() > 32
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        return None;
+    }
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, Timelike, Utc};
+    use teloxide::types::{ChatId, MessageId, ThreadId};
+
+    use super::*;
+    use crate::{
+        config::StorageConfig,
+        storage::{
+            tests::{chat, user},
+            Delivery,
+        },
+    };
+
+    const CHAT: ChatId = ChatId(-1001);
+
+    /// Storage with users 1 to 4 in [`CHAT`], 1 and 2 in group `dev`, 4 being
+    /// a bot
+    async fn storage() -> Storage {
+        let storage = Storage::init(&StorageConfig::Memory).await.unwrap();
+        for id in 1..=4 {
+            storage
+                .new_member(&chat(CHAT), &user(id, id == 4))
+                .await
+                .unwrap();
+        }
+        for id in 1..=2 {
+            storage.join_group(CHAT, UserId(id), "dev").await.unwrap();
+        }
+        storage
+    }
+
+    fn ids(members: &[Member]) -> Vec<&str> {
+        let mut ids: Vec<_> = members
+            .iter()
+            .map(|member| member.user_id.as_str())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn call(caller: Option<&User>, urgent: bool) -> Call<'_> {
+        Call {
+            caller,
+            text: "",
+            urgent,
+            tz: FixedOffset::east_opt(0).unwrap(),
+            limited: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn ping_args() {
+        let storage = storage().await;
+        let parse = |args| parse_ping_args(&storage, CHAT, args);
+        assert_eq!(parse("").await.unwrap(), (None, ""));
+        assert_eq!(parse("all").await.unwrap(), (None, ""));
+        assert_eq!(
+            parse("ALL релиз сломан").await.unwrap(),
+            (None, "релиз сломан")
+        );
+        assert_eq!(
+            parse("#Dev релиз сломан").await.unwrap(),
+            (Some("dev".to_owned()), "релиз сломан")
+        );
+        assert!(matches!(
+            parse("qa релиз сломан").await,
+            Err(PingArgsError::EmptyGroup(group)) if group == "qa"
+        ));
+        assert!(matches!(parse("релиз!").await, Err(PingArgsError::BadName)));
+
+        // group everyone left is as good as unknown
+        for id in 1..=2 {
+            storage.leave_group(CHAT, UserId(id), "dev").await.unwrap();
+        }
+        assert!(matches!(
+            parse("dev").await,
+            Err(PingArgsError::EmptyGroup(_))
+        ));
+    }
+
+    #[test]
+    fn urgent() {
+        assert_eq!(parse_urgent("! dev текст"), (true, "dev текст"));
+        assert_eq!(parse_urgent("!dev текст"), (false, "!dev текст"));
+        assert_eq!(parse_urgent(" dev "), (false, "dev"));
+    }
+
+    #[tokio::test]
+    async fn targets_of_group_topic_and_chat() {
+        let storage = storage().await;
+        let topic = ThreadId(MessageId(5));
+        let in_topic = Place {
+            chat_id: CHAT,
+            thread_id: Some(topic),
+        };
+        // bots are only left out later, by Targets
+        assert_eq!(
+            ids(&ping_targets(&storage, in_topic, None).await.unwrap()),
+            ["1", "2", "3"]
+        );
+        storage
+            .set_topic_follower(CHAT, topic, UserId(3), true)
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(&ping_targets(&storage, in_topic, None).await.unwrap()),
+            ["3"]
+        );
+        assert_eq!(
+            ids(&ping_targets(&storage, in_topic, Some("dev")).await.unwrap()),
+            ["1", "2"]
+        );
+        assert_eq!(
+            ids(&ping_targets(&storage, CHAT.into(), None).await.unwrap()),
+            ["1", "2", "3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn targets_by_delivery_and_quiet_hours() {
+        let storage = storage().await;
+        storage
+            .set_delivery(&user(2, false), Delivery::Dm)
+            .await
+            .unwrap();
+        storage
+            .set_delivery(&user(3, false), Delivery::Both)
+            .await
+            .unwrap();
+        // quiet for a few minutes around now
+        let now = Utc::now();
+        let minute = (now.hour() * 60 + now.minute()) as i32;
+        storage
+            .set_quiet_hours(
+                &user(3, false),
+                Some(((minute + 1439) % 1440, (minute + 2) % 1440)),
+            )
+            .await
+            .unwrap();
+        storage
+            .set_timezone(&user(3, false), Some(0))
+            .await
+            .unwrap();
+        let members: Vec<Member> = (1..=4).map(|id| Member::from(&user(id, id == 4))).collect();
+        let caller = user(1, false);
+
+        let targets =
+            Targets::new(&storage, CHAT, &call(Some(&caller), false), members.clone()).await;
+        assert_eq!(ids(&targets.members), ["2"]);
+        assert_eq!(ids(&targets.quiet), ["3"]);
+        assert!(targets.in_group().is_empty());
+        assert_eq!(targets.in_dm().len(), 1);
+
+        let targets = Targets::new(&storage, CHAT, &call(None, true), members).await;
+        assert_eq!(ids(&targets.members), ["1", "2", "3"]);
+        assert!(targets.quiet.is_empty());
+        assert_eq!(ids(&targets.in_group()), ["1", "3"]);
+        let in_dm: Vec<Member> = targets.in_dm().into_iter().cloned().collect();
+        assert_eq!(ids(&in_dm), ["2", "3"]);
+    }
+
+    #[test]
+    fn mentions_split_by_cap() {
+        let members: Vec<Member> = (1..=5).map(|id| Member::from(&user(id, false))).collect();
+        let header = header(&call(None, false));
+        let messages = mention_messages(&header, &members, 2);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(mention_messages(&header, &members, 40).len(), 1);
+        assert!(mention_messages(&header, &[], 40).is_empty());
+    }
+
+    #[test]
+    fn responses_fit_into_packed_message() {
+        let members: Vec<Member> = (1..=100).map(|id| Member::from(&user(id, false))).collect();
+        let text = "текст ".repeat(600);
+        let call = Call {
+            text: &text,
+            ..call(None, false)
+        };
+        let header = header(&call);
+        let messages = mention_messages(&header, &members, 100);
+        assert!(messages.len() > 1);
+        let mut first = header.clone();
+        first.append(&messages[0]);
+        // packed as full as first message may be
+        assert!(!Rendered::fit(&[&first, &messages[1]]));
+
+        let responder = |id| Responder {
+            member: Member::from(&user(id, false)),
+            response: PingResponse::Here,
+        };
+        let few: Vec<Responder> = (1..=10).map(responder).collect();
+        let edited = with_responses(first.clone(), &few);
+        assert!(Rendered::fit(&[&edited]));
+        assert!(edited.text.ends_with(&response_lines(&few)));
+
+        // more names than room left for them are shortened
+        let many: Vec<Responder> = (1..=500).map(responder).collect();
+        let edited = with_responses(first, &many);
+        assert!(Rendered::fit(&[&edited]));
+        assert!(edited.text.ends_with('…'));
+
+        // message Telegram took at full length still gets what fits
+        let full = Rendered::from("x".repeat(MAX_MESSAGE_LEN).as_str());
+        assert_eq!(with_responses(full, &few).len(), MAX_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn responses_listed_by_answer() {
+        let responder = |id, response| Responder {
+            member: Member::from(&user(id, false)),
+            response,
+        };
+        let responses = [
+            responder(1, PingResponse::Away),
+            responder(2, PingResponse::Here),
+            responder(3, PingResponse::Away),
+        ];
+        assert_eq!(
+            response_lines(&responses),
+            "\n\n✅ Тут: User2\n\n❌ Не смогут: User1, User3"
+        );
+        assert_eq!(response_lines(&[]), "");
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::Write;
+
+use chrono::FixedOffset;
+use teloxide::types::{ChatId, Message};
+use tracing::error;
+
+use super::{
+    ping::{
+        header, mention_messages, mentions_cap, parse_ping_args, parse_urgent, ping_targets,
+        send_rendered, Call, Targets,
+    },
+    render::Rendered,
+    reply, MyBot, Place,
+};
+use crate::storage::{Member, Storage};
+
+/// Sends caller privately who `/ping` with `args` would notify, and in how
+/// many messages, without notifying anyone
+pub(super) async fn ping_preview(
+    bot: &MyBot,
+    storage: &Storage,
+    msg: &Message,
+    tz: FixedOffset,
+    args: &str,
+) {
+    let Some(ref from) = msg.from else {
+        return;
+    };
+    let (urgent, args) = parse_urgent(args);
+    let (group, text) = match parse_ping_args(storage, msg.chat.id, args).await {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            reply(bot, msg, &err.to_string()).await;
+            return;
+        }
+    };
+    let members = match ping_targets(storage, Place::of(msg), group.as_deref()).await {
+        Ok(members) => members,
+        Err(err) => {
+            error!("failed getting members of {}: {}", msg.chat.id, err);
+            return;
+        }
+    };
+    let call = Call {
+        caller: Some(from),
+        text,
+        urgent,
+        tz,
+        limited: true,
+    };
+    let targets = Targets::new(storage, msg.chat.id, &call, members).await;
+    let settings = match storage.chat_settings(msg.chat.id).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            error!("failed getting chat settings: {}", err);
+            Default::default()
+        }
+    };
+    let (in_group, in_dm) = (targets.in_group(), targets.in_dm());
+    let messages = match mention_messages(&header(&call), &in_group, mentions_cap(&settings)).len()
+    {
+        0 if targets.members.is_empty() && targets.quiet.is_empty() => 0,
+        0 => 1,
+        messages => messages,
+    };
+
+    let mut summary = format!("Пинг в «{}»", msg.chat.title().unwrap_or("чате"));
+    if let Some(ref group) = group {
+        let _ = write!(summary, ", группа {}", group);
+    }
+    let _ = write!(
+        summary,
+        "\nУпомяну: {}, сообщений: {}",
+        in_group.len(),
+        messages
+    );
+    let mut lines = vec![summary];
+    let mut section = |title: &str, members: &[&Member]| {
+        if members.is_empty() {
+            return;
+        }
+        lines.push(format!("\n{} ({}):", title, members.len()));
+        lines.extend(members.iter().map(|member| member.full_name()));
+    };
+    section("Упомяну", &in_group.iter().collect::<Vec<_>>());
+    section("Напишу в личку", &in_dm);
+    section(
+        "Не побеспокою, тихие часы",
+        &targets.quiet.iter().collect::<Vec<_>>(),
+    );
+
+    // names only, so that nobody is notified even here
+    let mut parts = vec![Rendered::default()];
+    for line in lines {
+        let line = Rendered::from(format!("{}\n", line).as_str());
+        if !parts.last().is_some_and(|part| part.fits(&line)) {
+            parts.push(Rendered::default());
+        }
+        if let Some(part) = parts.last_mut() {
+            part.append(&line);
+        }
+    }
+    let dm = Place::from(ChatId::from(from.id));
+    for (i, part) in parts.into_iter().enumerate() {
+        if send_rendered(bot, dm, None, &Rendered::default(), part, None)
+            .await
+            .is_none()
+            && i == 0
+        {
+            reply(
+                bot,
+                msg,
+                "Не получилось написать в личку, сначала нажми /start у меня в личке",
+            )
+            .await;
+            return;
+        }
+    }
+}
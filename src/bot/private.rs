@@ -0,0 +1,245 @@
+use std::fmt::Write;
+
+use chrono::{FixedOffset, Timelike};
+
+use teloxide::{
+    prelude::*,
+    types::{Message, MessageId, ParseMode, User},
+    utils::{command::BotCommands, markdown},
+};
+use tracing::{debug, error, warn};
+use url::Url;
+
+use super::{message_request, reply, scheduler::parse_time, MyBot};
+use crate::{
+    config::Timezone,
+    storage::{Delivery, Member, Storage, UserSettings},
+};
+
+#[derive(BotCommands, Clone, Debug)]
+#[command(rename_rule = "snake_case", description = "Команды в личке:")]
+pub(super) enum PrivateCommand {
+    #[command(description = "начать")]
+    Start,
+
+    #[command(
+        description = "как получать пинги: /delivery group (в группе), dm (в личке) или both (и там, и там)"
+    )]
+    Delivery(String),
+
+    #[command(
+        description = "часовой пояс для тихих часов: /timezone +03:00, /timezone reset — как у бота"
+    )]
+    Timezone(String),
+
+    #[command(
+        description = "тихие часы, когда пинги только называют тебя, не беспокоя: /quiet 23:00-08:00 или off"
+    )]
+    Quiet(String),
+
+    #[command(description = "помощь")]
+    Help,
+}
+
+#[tracing::instrument(skip_all)]
+pub(super) async fn private_command_handler(
+    bot: MyBot,
+    msg: Message,
+    cmd: PrivateCommand,
+    storage: Storage,
+    tz: Timezone,
+) -> ResponseResult<()> {
+    debug!("private command: {:?}", cmd);
+    let Some(ref from) = msg.from else {
+        return Ok(());
+    };
+    match cmd {
+        PrivateCommand::Start | PrivateCommand::Help => {
+            let settings = storage.user_settings(from.id).await.unwrap_or_default();
+            let text = format!(
+                "Привет\\! Я пингую участников групп, куда меня добавили\\.\n\nПинги можно получать упоминанием в группе, здесь в личке или и там, и там\\. Сейчас: `{}`\n\n{}\n\n{}",
+                settings.delivery.as_str(),
+                describe_quiet_hours(&settings, tz.0),
+                markdown::escape(&PrivateCommand::descriptions().to_string()),
+            );
+            reply(&bot, &msg, &text).await;
+        }
+        PrivateCommand::Delivery(value) => {
+            let value = value.trim().to_lowercase();
+            if value.is_empty() {
+                let settings = storage.user_settings(from.id).await.unwrap_or_default();
+                reply(
+                    &bot,
+                    &msg,
+                    &format!("Сейчас: `{}`", settings.delivery.as_str()),
+                )
+                .await;
+                return Ok(());
+            }
+            let Ok(delivery) = value.parse::<Delivery>() else {
+                reply(&bot, &msg, "Можно `group`, `dm` или `both`").await;
+                return Ok(());
+            };
+            let text = match storage.set_delivery(from, delivery).await {
+                Ok(()) => match delivery {
+                    Delivery::Group => "Буду упоминать тебя в группах",
+                    Delivery::Dm => "Буду присылать пинги сюда, в группах упоминать не буду",
+                    Delivery::Both => "Буду упоминать тебя в группах и присылать пинги сюда",
+                },
+                Err(err) => {
+                    error!("failed setting delivery: {}", err);
+                    "Не получилось"
+                }
+            };
+            reply(&bot, &msg, text).await;
+        }
+        PrivateCommand::Timezone(value) => {
+            let value = value.trim();
+            if value.is_empty() {
+                let settings = storage.user_settings(from.id).await.unwrap_or_default();
+                reply(&bot, &msg, &describe_quiet_hours(&settings, tz.0)).await;
+                return Ok(());
+            }
+            let utc_offset = if value == "reset" {
+                None
+            } else {
+                match value.parse::<FixedOffset>() {
+                    Ok(offset) => Some(offset.local_minus_utc()),
+                    Err(_) => {
+                        reply(
+                            &bot,
+                            &msg,
+                            "Неправильный часовой пояс, нужно вроде `+03:00`",
+                        )
+                        .await;
+                        return Ok(());
+                    }
+                }
+            };
+            let text = match storage.set_timezone(from, utc_offset).await {
+                Ok(()) => {
+                    let settings = storage.user_settings(from.id).await.unwrap_or_default();
+                    describe_quiet_hours(&settings, tz.0)
+                }
+                Err(err) => {
+                    error!("failed setting timezone: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
+        PrivateCommand::Quiet(value) => {
+            let value = value.trim();
+            if value.is_empty() {
+                let settings = storage.user_settings(from.id).await.unwrap_or_default();
+                reply(&bot, &msg, &describe_quiet_hours(&settings, tz.0)).await;
+                return Ok(());
+            }
+            let quiet_hours = if value == "off" {
+                None
+            } else {
+                match parse_quiet_hours(value) {
+                    Some(quiet_hours) => Some(quiet_hours),
+                    None => {
+                        reply(&bot, &msg, "Нужно вроде `23:00\\-08:00` или `off`").await;
+                        return Ok(());
+                    }
+                }
+            };
+            let text = match storage.set_quiet_hours(from, quiet_hours).await {
+                Ok(()) => {
+                    let settings = storage.user_settings(from.id).await.unwrap_or_default();
+                    describe_quiet_hours(&settings, tz.0)
+                }
+                Err(err) => {
+                    error!("failed setting quiet hours: {}", err);
+                    "Не получилось".to_string()
+                }
+            };
+            reply(&bot, &msg, &text).await;
+        }
+    }
+    Ok(())
+}
+
+/// Parses quiet hours like `23:00-08:00` into minutes since midnight
+fn parse_quiet_hours(s: &str) -> Option<(i32, i32)> {
+    let (from, to) = s.split_once('-')?;
+    let minutes =
+        |time: &str| parse_time(time.trim()).map(|time| (time.hour() * 60 + time.minute()) as i32);
+    let (from, to) = (minutes(from)?, minutes(to)?);
+    (from != to).then_some((from, to))
+}
+
+/// Quiet hours and timezone of user, MarkdownV2
+fn describe_quiet_hours(settings: &UserSettings, default: FixedOffset) -> String {
+    let timezone = match settings.utc_offset {
+        Some(_) => format!("Часовой пояс: `{}`", settings.timezone(default)),
+        None => format!("Часовой пояс: `{}`, как у бота", default),
+    };
+    match settings.quiet_hours() {
+        Some((from, to)) => format!(
+            "{}\nТихие часы: `{:02}:{:02}\\-{:02}:{:02}`, пинги с `\\!` всё равно беспокоят",
+            timezone,
+            from / 60,
+            from % 60,
+            to / 60,
+            to % 60
+        ),
+        None => format!("{}\nТихих часов нет", timezone),
+    }
+}
+
+/// Private message about ping, MarkdownV2, with link to ping message `msg`
+/// when it was sent and chat allows linking
+pub(super) fn ping_dm(msg: Option<&Message>, caller: Option<&User>, text: &str) -> String {
+    let mut dm = match caller {
+        Some(u) => format!("{} зовёт тебя", markdown::escape(&u.full_name())),
+        None => "Тебя зовут".to_string(),
+    };
+    if let Some(title) = msg.and_then(|msg| msg.chat.title()) {
+        let _ = write!(dm, " в «{}»", markdown::escape(title));
+    }
+    if !text.is_empty() {
+        let _ = write!(dm, "\n\n{}", markdown::escape(text));
+    }
+    push_link(&mut dm, msg.and_then(Message::url));
+    dm
+}
+
+/// Private message reminding about ping `message_id` in `chat_id` nobody
+/// responded to, MarkdownV2
+pub(super) fn reminder_dm(chat_id: ChatId, message_id: MessageId) -> String {
+    let mut dm = "Ещё ждут твоего ответа на пинг".to_string();
+    push_link(&mut dm, Message::url_of(chat_id, None, message_id));
+    dm
+}
+
+fn push_link(dm: &mut String, url: Option<Url>) {
+    if let Some(url) = url {
+        let _ = write!(
+            dm,
+            "\n\n[К сообщению]({})",
+            markdown::escape_link_url(url.as_str())
+        );
+    }
+}
+
+/// Sends `dm`, MarkdownV2, to `members` privately. Returns those it could not
+/// be sent to, because they never started private chat with bot or blocked it.
+pub(super) async fn send_dms<'a>(bot: &MyBot, dm: &str, members: &[&'a Member]) -> Vec<&'a Member> {
+    let mut failed = Vec::new();
+    for &member in members {
+        let Ok(user_id) = member.user_id.parse().map(UserId) else {
+            continue;
+        };
+        if let Err(err) = message_request(bot, ChatId::from(user_id).into(), None, dm)
+            .parse_mode(ParseMode::MarkdownV2)
+            .await
+        {
+            warn!("failed sending private message to {}: {:?}", user_id, err);
+            failed.push(member);
+        }
+    }
+    failed
+}
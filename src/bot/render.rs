@@ -0,0 +1,173 @@
+use teloxide::types::{MessageEntity, MessageEntityKind, User, UserId};
+
+use crate::storage::Member;
+
+/// Longest message text Telegram accepts, in UTF-16 code units
+pub(super) const MAX_MESSAGE_LEN: usize = 4096;
+/// Most entities Telegram keeps in message
+pub(super) const MAX_ENTITIES: usize = 100;
+
+/// Plain text with entities, sent without parse mode so that names render as
+/// they are
+#[derive(Debug, Clone, Default)]
+pub(super) struct Rendered {
+    pub(super) text: String,
+    pub(super) entities: Vec<MessageEntity>,
+}
+
+impl From<&str> for Rendered {
+    fn from(text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+            entities: Vec::new(),
+        }
+    }
+}
+
+impl Rendered {
+    /// Length in UTF-16 code units, as Telegram counts it
+    pub(super) fn len(&self) -> usize {
+        utf16_len(&self.text)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// True if `other` appended still makes message Telegram accepts
+    pub(super) fn fits(&self, other: &Rendered) -> bool {
+        Self::fit(&[self, other])
+    }
+
+    /// True if `parts` appended one after another make message Telegram
+    /// accepts
+    pub(super) fn fit(parts: &[&Rendered]) -> bool {
+        Self::fit_in(parts, MAX_MESSAGE_LEN)
+    }
+
+    /// Same as [`Rendered::fit`], with text no longer than `len`
+    pub(super) fn fit_in(parts: &[&Rendered], len: usize) -> bool {
+        parts.iter().map(|part| part.len()).sum::<usize>() <= len
+            && parts.iter().map(|part| part.entities.len()).sum::<usize>() <= MAX_ENTITIES
+    }
+
+    pub(super) fn append(&mut self, other: &Rendered) -> &mut Self {
+        let offset = self.len();
+        self.entities
+            .extend(other.entities.iter().cloned().map(|mut entity| {
+                entity.offset += offset;
+                entity
+            }));
+        self.text.push_str(&other.text);
+        self
+    }
+
+    /// Splits text in two at start of its middle entity, None if there are
+    /// less than two entities
+    pub(super) fn split_in_half(&self) -> Option<(Rendered, Rendered)> {
+        if self.entities.len() < 2 {
+            return None;
+        }
+        let middle = self.entities.len() / 2;
+        let offset = self.entities[middle].offset;
+        let at = byte_index(&self.text, offset);
+        let mut head = Rendered {
+            text: self.text[..at].to_owned(),
+            entities: self.entities[..middle].to_vec(),
+        };
+        head.trim_end();
+        let mut tail = Rendered {
+            text: self.text[at..].to_owned(),
+            entities: self.entities[middle..].to_vec(),
+        };
+        for entity in &mut tail.entities {
+            entity.offset -= offset;
+        }
+        Some((head, tail))
+    }
+
+    pub(super) fn push(&mut self, text: &str) -> &mut Self {
+        self.text.push_str(text);
+        self
+    }
+
+    /// Appends `text` marked with entity of `kind`
+    pub(super) fn push_entity(&mut self, text: &str, kind: MessageEntityKind) -> &mut Self {
+        if !text.is_empty() {
+            self.entities
+                .push(MessageEntity::new(kind, self.len(), utf16_len(text)));
+            self.text.push_str(text);
+        }
+        self
+    }
+
+    /// Appends `@username`, or name linked to user when there is no username
+    pub(super) fn push_mention(&mut self, user: &User) -> &mut Self {
+        match user.username {
+            Some(ref username) => {
+                self.push_entity(&format!("@{}", username), MessageEntityKind::Mention)
+            }
+            None => self.push_entity(
+                &user.full_name(),
+                MessageEntityKind::TextMention { user: user.clone() },
+            ),
+        }
+    }
+
+    /// Appends `text` linked to user with `user_id`, who may be unknown
+    pub(super) fn push_user_link(&mut self, text: &str, user_id: UserId) -> &mut Self {
+        self.push_entity(text, MessageEntityKind::TextLink { url: user_id.url() })
+    }
+
+    pub(super) fn push_member(&mut self, member: &Member) -> &mut Self {
+        self.push_mention(&User::from(member.clone()))
+    }
+
+    /// Drops whitespace at the end
+    pub(super) fn trim_end(&mut self) -> &mut Self {
+        let len = self.text.trim_end().len();
+        self.text.truncate(len);
+        self.clamp_entities();
+        self
+    }
+
+    /// Cuts text to `len` UTF-16 code units, ending it with `…` when cut
+    pub(super) fn truncate(&mut self, len: usize) -> &mut Self {
+        if self.len() <= len {
+            return self;
+        }
+        let at = byte_index(&self.text, len.saturating_sub(1));
+        self.text.truncate(at);
+        self.clamp_entities();
+        if len > 0 {
+            self.text.push('…');
+        }
+        self
+    }
+
+    /// Drops or shortens entities past the end of text
+    fn clamp_entities(&mut self) {
+        let len = self.len();
+        self.entities.retain(|entity| entity.offset < len);
+        for entity in &mut self.entities {
+            entity.length = entity.length.min(len - entity.offset);
+        }
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Byte index of `text` at `offset` UTF-16 code units, or of character
+/// containing it
+fn byte_index(text: &str, offset: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        units += c.len_utf16();
+        if units > offset {
+            return i;
+        }
+    }
+    text.len()
+}
@@ -0,0 +1,124 @@
+use std::fmt::Write;
+
+use teloxide::{
+    prelude::*,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message},
+};
+use tracing::{error, warn};
+
+use super::{message_request, MyBot, Place};
+use crate::storage::{Member, Storage};
+
+/// Prefix of callback data of page buttons
+const WHO_PREFIX: &str = "who:";
+/// Names per page, long names still fit into one message
+const PAGE_SIZE: usize = 20;
+
+/// Replies with first page of those who would be pinged, naming them without
+/// mentions so that nobody is notified
+pub(super) async fn who(bot: &MyBot, storage: &Storage, msg: &Message) {
+    let members = match roster(storage, msg.chat.id).await {
+        Ok(members) => members,
+        Err(err) => {
+            error!("failed getting chat members: {}", err);
+            return;
+        }
+    };
+    let (text, keyboard) = page(&members, 0);
+    let mut request =
+        message_request(bot, Place::of(msg), Some(msg.id), &text).disable_notification(true);
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    if let Err(err) = request.await {
+        warn!("failed sending message: {:?}", err);
+    }
+}
+
+/// Shows page asked for with roster button. Returns false if query is not
+/// about roster.
+pub(super) async fn turn_page(bot: &MyBot, storage: &Storage, q: &CallbackQuery) -> bool {
+    let Some(page_no) = q
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(WHO_PREFIX))
+        .and_then(|page_no| page_no.parse::<usize>().ok())
+    else {
+        return false;
+    };
+    let Some(ref message) = q.message else {
+        return true;
+    };
+    let (chat_id, message_id) = (message.chat().id, message.id());
+    let members = match roster(storage, chat_id).await {
+        Ok(members) => members,
+        Err(err) => {
+            error!("failed getting chat members: {}", err);
+            return true;
+        }
+    };
+    let (text, keyboard) = page(&members, page_no);
+    let mut request = bot.edit_message_text(chat_id, message_id, text);
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    if let Err(err) = request.await {
+        warn!("failed editing roster message: {:?}", err);
+    }
+    true
+}
+
+/// Members of chat who would be pinged, by name
+async fn roster(storage: &Storage, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error> {
+    let mut members: Vec<Member> = storage
+        .chat_members(chat_id)
+        .await?
+        .into_iter()
+        .filter(|member| !member.is_bot)
+        .collect();
+    members.sort_by_cached_key(|member| member.full_name().to_lowercase());
+    Ok(members)
+}
+
+/// Plain text of page `page_no` of `members`, with buttons to neighbouring
+/// pages when there are any
+fn page(members: &[Member], page_no: usize) -> (String, Option<InlineKeyboardMarkup>) {
+    if members.is_empty() {
+        return ("Тут некого пинговать".to_string(), None);
+    }
+    let pages = members.len().div_ceil(PAGE_SIZE);
+    let page_no = page_no.min(pages - 1);
+    let mut text = format!("Пингую {} пользователей", members.len());
+    if pages > 1 {
+        let _ = write!(text, ", страница {} из {}", page_no + 1, pages);
+    }
+    text.push_str(":\n");
+    for (i, member) in members
+        .iter()
+        .enumerate()
+        .skip(page_no * PAGE_SIZE)
+        .take(PAGE_SIZE)
+    {
+        let _ = write!(text, "\n{}. {}", i + 1, member.full_name());
+        if let Some(ref username) = member.username {
+            // without @, which would mention
+            let _ = write!(text, " ({})", username);
+        }
+    }
+
+    let mut buttons = Vec::new();
+    if page_no > 0 {
+        buttons.push(InlineKeyboardButton::callback(
+            "← Назад",
+            format!("{}{}", WHO_PREFIX, page_no - 1),
+        ));
+    }
+    if page_no + 1 < pages {
+        buttons.push(InlineKeyboardButton::callback(
+            "Дальше →",
+            format!("{}{}", WHO_PREFIX, page_no + 1),
+        ));
+    }
+    let keyboard = (!buttons.is_empty()).then(|| InlineKeyboardMarkup::new([buttons]));
+    (text, keyboard)
+}
@@ -0,0 +1,319 @@
+use std::{
+    fmt::{self, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::{DateTime, Datelike, Days, FixedOffset, NaiveTime, Utc, Weekday};
+use teloxide::{
+    types::{ChatId, Message, MessageId, ThreadId, UserId},
+    utils::markdown,
+};
+use tracing::{debug, error, info};
+
+use super::{
+    confirm::PendingPings,
+    limits::{record_ping, within_limits},
+    ping::{parse_ping_args, ping_members, ping_targets, Call},
+    reply, send, topic, MyBot, Place,
+};
+use crate::storage::{ScheduledPing, Storage};
+
+/// How often due pings are looked for
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Most scheduled pings chat may have
+const MAX_SCHEDULED_PINGS: usize = 20;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("mon", Weekday::Mon),
+    ("tue", Weekday::Tue),
+    ("wed", Weekday::Wed),
+    ("thu", Weekday::Thu),
+    ("fri", Weekday::Fri),
+    ("sat", Weekday::Sat),
+    ("sun", Weekday::Sun),
+];
+
+/// Days of week and time of day, written like `weekdays 10:00`, `daily 9:30`
+/// or `mon,thu 18:00`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Schedule {
+    /// Indexed by days from monday
+    days: [bool; 7],
+    time: NaiveTime,
+}
+
+impl Schedule {
+    /// Every day at `time`
+    pub(super) fn daily(time: NaiveTime) -> Self {
+        Self {
+            days: [true; 7],
+            time,
+        }
+    }
+
+    /// First moment matching schedule strictly after `after`, time of day
+    /// being at offset `tz`. Offset is fixed, so runs shift against local
+    /// time where daylight saving time changes it.
+    pub(super) fn next_after(&self, after: DateTime<Utc>, tz: FixedOffset) -> DateTime<Utc> {
+        let local = after.with_timezone(&tz);
+        (0..=7)
+            .filter_map(|offset| local.date_naive().checked_add_days(Days::new(offset)))
+            .filter(|date| self.days[date.weekday().num_days_from_monday() as usize])
+            .filter_map(|date| date.and_time(self.time).and_local_timezone(tz).single())
+            .find(|run| *run > local)
+            .expect("schedule has at least one day")
+            .with_timezone(&Utc)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (days, time) = s.trim().split_once(char::is_whitespace).ok_or(())?;
+        let time = parse_time(time.trim()).ok_or(())?;
+        let days = match days.to_lowercase().as_str() {
+            "daily" => [true; 7],
+            "weekdays" => [true, true, true, true, true, false, false],
+            "weekends" => [false, false, false, false, false, true, true],
+            list => {
+                let mut days = [false; 7];
+                for day in list.split(',') {
+                    let (_, weekday) = WEEKDAYS.iter().find(|(name, _)| *name == day).ok_or(())?;
+                    days[weekday.num_days_from_monday() as usize] = true;
+                }
+                days
+            }
+        };
+        Ok(Self { days, time })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.days {
+            [true, true, true, true, true, true, true] => write!(f, "daily")?,
+            [true, true, true, true, true, false, false] => write!(f, "weekdays")?,
+            [false, false, false, false, false, true, true] => write!(f, "weekends")?,
+            days => {
+                let names: Vec<&str> = WEEKDAYS
+                    .iter()
+                    .zip(days)
+                    .filter(|(_, on)| *on)
+                    .map(|((name, _), _)| *name)
+                    .collect();
+                write!(f, "{}", names.join(","))?
+            }
+        }
+        write!(f, " {}", self.time.format("%H:%M"))
+    }
+}
+
+/// Parses time of day like `9:30` or `18:00`
+pub(super) fn parse_time(s: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M").ok()
+}
+
+/// Sends due scheduled pings, forever
+pub(super) async fn run_scheduler(bot: MyBot, storage: Storage, tz: FixedOffset) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let due = match storage.due_scheduled_pings(now.timestamp()).await {
+            Ok(due) => due,
+            Err(err) => {
+                error!("failed getting due pings: {}", err);
+                continue;
+            }
+        };
+        for ping in due {
+            // reschedule before sending, so that failure midway doesn't repeat
+            // ping, and only the instance that did sends it
+            match reschedule(&storage, &ping, now, tz).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!("scheduled ping {} was taken by another instance", ping.id);
+                    continue;
+                }
+                Err(err) => {
+                    error!("failed rescheduling ping {}: {}", ping.id, err);
+                    continue;
+                }
+            }
+            let chat_id = ChatId(ping.chat_id);
+            let created_by = ping.created_by.parse().ok().map(UserId);
+            if let Some(user_id) = created_by {
+                if !within_limits(&storage, chat_id, user_id, now.timestamp()).await {
+                    info!(
+                        "skipping scheduled ping {}, its creator is over limits",
+                        ping.id
+                    );
+                    continue;
+                }
+            }
+            debug!("sending scheduled ping {}", ping.id);
+            let place = Place {
+                chat_id,
+                thread_id: ping
+                    .thread_id
+                    .map(|thread_id| ThreadId(MessageId(thread_id))),
+            };
+            let members = match ping_targets(&storage, place, ping.group_name.as_deref()).await {
+                Ok(members) => members,
+                Err(err) => {
+                    error!("failed getting members of {}: {}", chat_id, err);
+                    continue;
+                }
+            };
+            let call = Call {
+                caller: None,
+                text: &ping.text,
+                urgent: false,
+                tz,
+                limited: true,
+            };
+            if ping_members(&bot, &storage, place, None, call, members).await {
+                if let Some(user_id) = created_by {
+                    record_ping(&storage, chat_id, user_id, now.timestamp()).await;
+                }
+            }
+        }
+    }
+}
+
+/// Moves recurring ping to its next run, deletes one-off ping. Returns false
+/// if ping was already moved or deleted, by another instance sharing database.
+async fn reschedule(
+    storage: &Storage,
+    ping: &ScheduledPing,
+    now: DateTime<Utc>,
+    tz: FixedOffset,
+) -> Result<bool, sqlx::Error> {
+    match ping.schedule.as_deref().map(Schedule::from_str) {
+        Some(Ok(schedule)) => {
+            let next_run = schedule.next_after(now, tz).timestamp();
+            storage
+                .reschedule_ping(ping.id, ping.next_run, next_run)
+                .await
+        }
+        Some(Err(())) => {
+            error!("invalid schedule of ping {}: {:?}", ping.id, ping.schedule);
+            storage
+                .delete_scheduled_ping(ChatId(ping.chat_id), ping.id)
+                .await
+        }
+        None => {
+            storage
+                .delete_scheduled_ping(ChatId(ping.chat_id), ping.id)
+                .await
+        }
+    }
+}
+
+/// Adds ping, first sent at `next_run`, replies with its number. Pings of
+/// more people than chat allows at once are added once caller confirms them.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn add_scheduled_ping(
+    bot: &MyBot,
+    storage: &Storage,
+    pending: &PendingPings,
+    msg: &Message,
+    tz: FixedOffset,
+    schedule: Option<Schedule>,
+    next_run: DateTime<Utc>,
+    args: &str,
+) {
+    let Some(ref from) = msg.from else {
+        return;
+    };
+    match storage.chat_scheduled_pings(msg.chat.id).await {
+        Ok(pings) if pings.len() >= MAX_SCHEDULED_PINGS => {
+            reply(
+                bot,
+                msg,
+                &format!(
+                    "В чате уже `{}` пингов по расписанию, сначала отмени ненужные через /unschedule",
+                    pings.len()
+                ),
+            )
+            .await;
+            return;
+        }
+        Ok(_) => {}
+        Err(err) => {
+            error!("failed getting scheduled pings: {}", err);
+            return;
+        }
+    }
+    let (group_name, text) = match parse_ping_args(storage, msg.chat.id, args).await {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            reply(bot, msg, &err.to_string()).await;
+            return;
+        }
+    };
+    let ping = ScheduledPing {
+        id: 0,
+        chat_id: msg.chat.id.0,
+        created_by: from.id.to_string(),
+        schedule: schedule.map(|schedule| schedule.to_string()),
+        next_run: next_run.timestamp(),
+        group_name,
+        text: text.to_owned(),
+        thread_id: topic(msg).map(|thread_id| thread_id.0 .0),
+    };
+    pending
+        .schedule(bot, storage, Some(from), Place::of(msg), msg.id, ping, tz)
+        .await;
+}
+
+/// Saves scheduled ping, replying to `reply_to` with its number
+pub(super) async fn save_scheduled_ping(
+    bot: &MyBot,
+    storage: &Storage,
+    place: Place,
+    reply_to: MessageId,
+    ping: &ScheduledPing,
+    tz: FixedOffset,
+) {
+    let text = match storage.add_scheduled_ping(ping).await {
+        Ok(id) => format!(
+            "Запланировал пинг `{}`, ближайший в `{}`",
+            id,
+            DateTime::from_timestamp(ping.next_run, 0)
+                .unwrap_or_default()
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M")
+        ),
+        Err(err) => {
+            error!("failed adding scheduled ping: {}", err);
+            "Не получилось".to_string()
+        }
+    };
+    send(bot, place, Some(reply_to), &text).await;
+}
+
+/// One line description of ping for listing, MarkdownV2
+pub(super) fn describe(ping: &ScheduledPing, tz: FixedOffset) -> String {
+    let mut buf = format!(
+        "`{}`: `{}`",
+        ping.id,
+        ping.schedule.as_deref().unwrap_or("once")
+    );
+    if let Some(ref group) = ping.group_name {
+        let _ = write!(buf, ", группа `{}`", markdown::escape_code(group));
+    }
+    if let Some(next_run) = DateTime::from_timestamp(ping.next_run, 0) {
+        let _ = write!(
+            buf,
+            ", ближайший `{}`",
+            next_run.with_timezone(&tz).format("%Y-%m-%d %H:%M")
+        );
+    }
+    if !ping.text.is_empty() {
+        let _ = write!(buf, "\n{}", markdown::escape(&ping.text));
+    }
+    buf
+}
@@ -0,0 +1,112 @@
+/// Most phrases chat may have
+pub(super) const MAX_TRIGGERS: usize = 20;
+/// Longest phrase, in characters
+const MAX_TRIGGER_LEN: usize = 64;
+
+/// Normalizes trigger phrase, returns None if phrase is not valid
+pub(super) fn trigger_phrase(phrase: &str) -> Option<String> {
+    let phrase = phrase
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    if phrase.is_empty() || phrase.starts_with('/') || phrase.chars().count() > MAX_TRIGGER_LEN {
+        return None;
+    }
+    Some(phrase)
+}
+
+/// Text without first of `triggers` found in it as whole words, None if there
+/// are none
+pub(super) fn strip_trigger(text: &str, triggers: &[String]) -> Option<String> {
+    triggers.iter().find_map(|phrase| {
+        let (start, end) = find_phrase(text, phrase)?;
+        let rest = format!("{} {}", text[..start].trim(), text[end..].trim());
+        Some(rest.trim().to_owned())
+    })
+}
+
+/// Byte range of `phrase` in `text` as whole words, ignoring case, space in
+/// phrase matches any run of whitespace
+fn find_phrase(text: &str, phrase: &str) -> Option<(usize, usize)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let phrase: Vec<char> = phrase.chars().collect();
+    if phrase.is_empty() {
+        return None;
+    }
+    (0..chars.len()).find_map(|start| {
+        if start > 0 && is_word_char(chars[start - 1].1) {
+            return None;
+        }
+        let end = start + match_len(&chars[start..], &phrase)?;
+        chars
+            .get(end)
+            .is_none_or(|&(_, c)| !is_word_char(c))
+            .then(|| (chars[start].0, chars.get(end).map_or(text.len(), |c| c.0)))
+    })
+}
+
+/// How many of `chars` match `phrase` from their start, None if they don't
+fn match_len(chars: &[(usize, char)], phrase: &[char]) -> Option<usize> {
+    let mut len = 0;
+    for &p in phrase {
+        if p.is_whitespace() {
+            let spaces = chars[len..]
+                .iter()
+                .take_while(|(_, c)| c.is_whitespace())
+                .count();
+            if spaces == 0 {
+                return None;
+            }
+            len += spaces;
+        } else {
+            let &(_, c) = chars.get(len)?;
+            if !c.to_lowercase().eq(p.to_lowercase()) {
+                return None;
+            }
+            len += 1;
+        }
+    }
+    Some(len)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(text: &str, phrase: &str) -> Option<String> {
+        strip_trigger(text, &[trigger_phrase(phrase).unwrap()])
+    }
+
+    #[test]
+    fn phrase_is_normalized() {
+        assert_eq!(
+            trigger_phrase("  Все \t СЮДА "),
+            Some("все сюда".to_owned())
+        );
+        assert_eq!(trigger_phrase("/ping"), None);
+        assert_eq!(trigger_phrase(" "), None);
+    }
+
+    #[test]
+    fn whole_words_in_any_case_and_spacing() {
+        assert_eq!(strip("Все сюда", "все сюда"), Some("".to_owned()));
+        assert_eq!(
+            strip("релиз сломан, ВСЕ   сюда!", "все сюда"),
+            Some("релиз сломан, !".to_owned())
+        );
+        assert_eq!(
+            strip("все\nсюда срочно", "все сюда"),
+            Some("срочно".to_owned())
+        );
+        assert_eq!(strip("@all глянь", "@all"), Some("глянь".to_owned()));
+        assert_eq!(strip("все сюдаа", "все сюда"), None);
+        assert_eq!(strip("почти все сюда", "чти все сюда"), None);
+        assert_eq!(strip("mail@all.ru", "@all"), None);
+        assert_eq!(strip("всесюда", "все сюда"), None);
+    }
+}
@@ -1,5 +1,6 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, num::NonZeroU64, path::PathBuf};
 
+use chrono::FixedOffset;
 use clap::Parser;
 use serde::Deserialize;
 use url::Url;
@@ -19,7 +20,28 @@ impl Args {
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub bot: BotConfig,
-    pub storage: PathBuf,
+    pub storage: StorageConfig,
+}
+
+/// Either path to SQLite database, PostgreSQL connection url or `:memory:`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "String")]
+pub enum StorageConfig {
+    Sqlite(PathBuf),
+    Postgres(String),
+    Memory,
+}
+
+impl From<String> for StorageConfig {
+    fn from(value: String) -> Self {
+        if value == ":memory:" {
+            Self::Memory
+        } else if value.starts_with("postgres://") || value.starts_with("postgresql://") {
+            Self::Postgres(value)
+        } else {
+            Self::Sqlite(value.into())
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -27,6 +49,32 @@ pub struct BotConfig {
     pub token: String,
     pub admin_id: Option<i64>,
     pub webhook: Option<WebhookConfig>,
+    pub revalidate: Option<RevalidateConfig>,
+    #[serde(default)]
+    pub timezone: Timezone,
+}
+
+/// Fixed UTC offset like `+03:00`, used for scheduled pings. Daylight saving
+/// time is not followed, the offset stays the same all year.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Timezone(pub FixedOffset);
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Self(FixedOffset::east_opt(0).unwrap())
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .parse()
+            .map(Self)
+            .map_err(|err| format!("invalid timezone {:?}: {}", value, err))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -35,6 +83,14 @@ pub struct WebhookConfig {
     pub address: SocketAddr,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct RevalidateConfig {
+    /// Seconds between sweeps, 0 is refused
+    pub interval: NonZeroU64,
+    /// Milliseconds between member checks
+    pub delay: u64,
+}
+
 impl Config {
     fn parse_file(path: PathBuf) -> Self {
         let yaml_content = std::fs::read_to_string(path).expect("Failed to read config file");
@@ -1,197 +1,328 @@
-use std::path::Path;
+use std::{fmt, ops::Deref, str::FromStr, sync::Arc};
 
-use sqlx::{sqlite::SqliteConnectOptions, Row, SqlitePool};
-use teloxide::types::{Chat, ChatId, ChatKind, ChatPublic, PublicChatKind, User, UserId};
-use tracing::{debug, info, trace};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, Timelike, Utc};
+use teloxide::types::{
+    Chat, ChatId, ChatKind, ChatPublic, MessageId, PublicChatKind, ThreadId, User, UserId,
+};
+use tracing::debug;
 
-#[derive(Debug, Clone)]
-pub struct Storage {
-    pool: sqlx::Pool<sqlx::Sqlite>,
-}
+use crate::config::StorageConfig;
 
+mod memory;
+mod migrations;
+mod postgres;
+mod sqlite;
+#[cfg(test)]
+pub(crate) mod tests;
 mod v01;
 
+/// Tables keyed by `chat_id` along with the rest of their primary keys, moved
+/// to new id when group becomes supergroup. Keep `MemoryStorage::migrate_chat`
+/// in sync.
+const CHAT_TABLES: &[(&str, &[&str])] = &[
+    ("chats", &[]),
+    ("chat_members", &["user_id"]),
+    ("group_members", &["group_name", "user_id"]),
+    ("muted_members", &["user_id"]),
+    ("scheduled_pings", &["id"]),
+    ("chat_settings", &[]),
+    ("ping_log", &["user_id", "sent_at"]),
+    ("ping_allowlist", &["user_id"]),
+    ("topic_followers", &["thread_id", "user_id"]),
+    ("ping_triggers", &["phrase"]),
+];
+
+/// Tables keyed by message ids of chat, which mean nothing in supergroup made
+/// of it, so their rows are deleted instead of moved
+const MESSAGE_TABLES: &[&str] = &["pings", "ping_responses", "ping_targets"];
+
+/// Seconds pings are remembered for, enough for daily quota
+const PING_LOG_TTL: i64 = 24 * 60 * 60;
+/// Seconds sent pings and responses to them are remembered for
+const SENT_PING_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// Storage shared between handlers, backed by one of [`Backend`]
+/// implementations
+#[derive(Clone)]
+pub struct Storage(Arc<dyn Backend>);
+
 impl Storage {
-    pub async fn init(file: &Path) -> Result<Self, Box<dyn std::error::Error>> {
-        debug!("init storage");
-        let opts = SqliteConnectOptions::new()
-            .filename(file)
-            .create_if_missing(true);
-        let pool = SqlitePool::connect_with(opts).await?;
-        Self::create_tables(&pool).await?;
-        Ok(Self { pool })
-    }
-
-    async fn create_tables(pool: &sqlx::Pool<sqlx::Sqlite>) -> Result<(), sqlx::Error> {
-        trace!("try create tables");
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS chat_members (
-                chat_id INTEGER NOT NULL,
-                user_id TEXT NOT NULL,
-                PRIMARY KEY (chat_id, user_id)
-            )",
-        )
-        .execute(pool)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS users (
-                user_id TEXT NOT NULL,
-                is_bot BOOLEAN NOT NULL DEFAULT FALSE,
-                username TEXT,
-                first_name TEXT NOT NULL,
-                last_name TEXT,
-                language TEXT,
-                is_premium BOOLEAN NOT NULL DEFAULT FALSE,
-                added_to_attachment_menu BOOLEAN NOT NULL DEFAULT FALSE,
-                PRIMARY KEY (user_id)
-            )",
-        )
-        .execute(pool)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS chats (
-                chat_id INTEGER NOT NULL,
-                title TEXT,
-                username TEXT,
-                PRIMARY KEY (chat_id)
-            )",
-        )
-        .execute(pool)
-        .await?;
-
-        Ok(())
-    }
-
-    pub(crate) async fn new_member(&self, chat: &Chat, user: &User) -> Result<(), sqlx::Error> {
-        let ChatKind::Public(ChatPublic {
-            kind: ref public_chat_kind,
-            ..
-        }) = chat.kind
-        else {
-            debug!("chat is not public");
-            return Ok(());
+    pub async fn init(config: &StorageConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let backend: Arc<dyn Backend> = match config {
+            StorageConfig::Sqlite(file) => Arc::new(sqlite::SqliteStorage::init(file).await?),
+            StorageConfig::Postgres(url) => Arc::new(postgres::PostgresStorage::init(url).await?),
+            StorageConfig::Memory => Arc::new(memory::MemoryStorage::new()),
         };
-        if let PublicChatKind::Channel(_) = public_chat_kind {
-            debug!("chat is channel");
-            return Ok(());
-        }
-        debug!("new member chat_id: {} user_id: {}", chat.id, user.id);
-
-        let user_id = user.id.to_string();
-        sqlx::query(
-            "INSERT
-            INTO users (
-                user_id, is_bot, username, first_name,
-                last_name, language, is_premium, added_to_attachment_menu
-                )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT (user_id)
-            DO UPDATE SET
-                is_bot = EXCLUDED.is_bot,
-                username = EXCLUDED.username,
-                first_name = EXCLUDED.first_name,
-                last_name = EXCLUDED.last_name,
-                language = EXCLUDED.language,
-                is_premium = EXCLUDED.is_premium,
-                added_to_attachment_menu = EXCLUDED.added_to_attachment_menu",
-        )
-        .bind(&user_id)
-        .bind(user.is_bot)
-        .bind(&user.username)
-        .bind(&user.first_name)
-        .bind(&user.last_name)
-        .bind(&user.language_code)
-        .bind(user.is_premium)
-        .bind(user.added_to_attachment_menu)
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            "INSERT
-            INTO chats (chat_id, title, username)
-            VALUES (?, ?, ?)
-            ON CONFLICT (chat_id)
-            DO UPDATE SET
-                title = EXCLUDED.title,
-                username = EXCLUDED.username",
-        )
-        .bind(chat.id.0)
-        .bind(chat.title())
-        .bind(chat.username())
-        .execute(&self.pool)
-        .await?;
-
-        let result =
-            sqlx::query("INSERT OR IGNORE INTO chat_members (chat_id, user_id) VALUES (?, ?)")
-                .bind(chat.id.0)
-                .bind(&user_id)
-                .execute(&self.pool)
-                .await?;
-        if result.rows_affected() > 0 {
-            info!(
-                "added member chat_id: {} user_id: {} username: {:?} first_name: {} last_name: {}",
-                chat.id,
-                user.id,
-                &user.username.as_ref().map_or("<none>", |v| v),
-                &user.first_name,
-                &user.last_name.as_ref().map_or("<none>", |v| v)
-            );
-        }
-        Ok(())
+        Ok(Self(backend))
+    }
+}
+
+impl Deref for Storage {
+    type Target = dyn Backend;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
     }
+}
+
+impl fmt::Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Storage").field(&self.0).finish()
+    }
+}
+
+#[async_trait]
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Remembers user as member of chat, ignores private chats and channels
+    async fn new_member(&self, chat: &Chat, user: &User) -> Result<(), sqlx::Error>;
+
+    /// Same as [`Backend::new_member`], also records that user was active in
+    /// chat at `date` (unix time)
+    async fn member_seen(&self, chat: &Chat, user: &User, date: i64) -> Result<(), sqlx::Error>;
+
+    async fn delete_member(&self, chat_id: ChatId, user_id: UserId) -> Result<(), sqlx::Error>;
+
+    /// Members to ping, without bots and muted ones
+    async fn chat_members(&self, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error>;
+
+    /// Same as [`Backend::chat_members`], only those seen since `since` (unix time)
+    async fn active_members(&self, chat_id: ChatId, since: i64)
+        -> Result<Vec<Member>, sqlx::Error>;
+
+    async fn chat_members_count(&self, chat_id: ChatId) -> Result<u64, sqlx::Error>;
+
+    async fn chats_with_counts(&self) -> Result<Vec<(i64, Option<String>, u64)>, sqlx::Error>;
+
+    async fn tracked_chats(&self) -> Result<Vec<ChatId>, sqlx::Error>;
 
-    pub(crate) async fn delete_member(
+    /// All tracked user ids of chat, including muted ones and bots
+    async fn chat_member_ids(&self, chat_id: ChatId) -> Result<Vec<UserId>, sqlx::Error>;
+
+    /// Returns true if user was not in group before
+    async fn join_group(
         &self,
         chat_id: ChatId,
         user_id: UserId,
-    ) -> Result<(), sqlx::Error> {
-        debug!("delete member chat_id: {} user_id: {}", chat_id, user_id);
-        let result = sqlx::query("DELETE FROM chat_members WHERE chat_id = ? AND user_id = ?")
-            .bind(chat_id.0)
-            .bind(user_id.to_string())
-            .execute(&self.pool)
-            .await?;
-        if result.rows_affected() > 0 {
-            info!("deleted member chat_id: {} user_id: {}", chat_id, user_id);
-        }
-        Ok(())
-    }
+        group: &str,
+    ) -> Result<bool, sqlx::Error>;
 
-    pub(crate) async fn chat_members(&self, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error> {
-        sqlx::query_as("SELECT u.* FROM chat_members cm JOIN users u ON cm.user_id = u.user_id WHERE cm.chat_id = ? AND NOT(u.is_bot)")
-            .bind(chat_id.0)
-            .fetch_all(&self.pool)
-            .await
-    }
+    /// Returns true if user was in group before
+    async fn leave_group(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        group: &str,
+    ) -> Result<bool, sqlx::Error>;
 
-    pub(crate) async fn chat_members_count(&self, chat_id: ChatId) -> Result<u64, sqlx::Error> {
-        sqlx::query("SELECT COUNT(*) FROM chat_members cm JOIN users u ON cm.user_id = u.user_id WHERE cm.chat_id = ? AND NOT(u.is_bot)")
-            .bind(chat_id.0)
-            .fetch_one(&self.pool)
-            .await
-            .map(|row| row.get(0))
-    }
+    async fn group_members(&self, chat_id: ChatId, group: &str)
+        -> Result<Vec<Member>, sqlx::Error>;
+
+    async fn chat_groups(&self, chat_id: ChatId) -> Result<Vec<(String, u64)>, sqlx::Error>;
+
+    /// Returns true if muted flag was changed
+    async fn set_muted(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        muted: bool,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Moves everything known about chat `from` to chat `to`, except sent
+    /// pings with their responses and targets, which are dropped since their
+    /// message ids mean nothing in chat `to`
+    async fn migrate_chat(&self, from: ChatId, to: ChatId) -> Result<(), sqlx::Error>;
+
+    /// Returns id of added ping, `id` of `ping` is ignored
+    async fn add_scheduled_ping(&self, ping: &ScheduledPing) -> Result<i64, sqlx::Error>;
+
+    async fn chat_scheduled_pings(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Vec<ScheduledPing>, sqlx::Error>;
+
+    /// Pings with `next_run` not after `now` (unix time)
+    async fn due_scheduled_pings(&self, now: i64) -> Result<Vec<ScheduledPing>, sqlx::Error>;
+
+    /// Moves ping from `old_next_run` to `next_run`, returns false if it is not
+    /// there anymore or was moved already, by another instance sharing
+    /// database
+    async fn reschedule_ping(
+        &self,
+        id: i64,
+        old_next_run: i64,
+        next_run: i64,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Returns true if ping was there
+    async fn delete_scheduled_ping(&self, chat_id: ChatId, id: i64) -> Result<bool, sqlx::Error>;
+
+    /// Settings of chat, defaults if never saved
+    async fn chat_settings(&self, chat_id: ChatId) -> Result<ChatSettings, sqlx::Error>;
 
-    pub(crate) async fn chats_with_counts(
+    async fn save_chat_settings(
         &self,
-    ) -> Result<Vec<(i64, Option<String>, u64)>, sqlx::Error> {
-        sqlx::query_as("SELECT c.chat_id, c.title, COUNT(cm.user_id) FROM chats c JOIN chat_members cm ON c.chat_id = cm.chat_id LEFT JOIN users u ON cm.user_id = u.user_id WHERE NOT u.is_bot ORDER BY c.chat_id")
-            .fetch_all(&self.pool)
-            .await
+        chat_id: ChatId,
+        settings: &ChatSettings,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Records ping by user at `sent_at` (unix time), forgets pings older than a day
+    async fn log_ping(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        sent_at: i64,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Unix time of last ping in chat
+    async fn last_ping(&self, chat_id: ChatId) -> Result<Option<i64>, sqlx::Error>;
+
+    /// Unix times of pings by user since `since`, oldest first
+    async fn user_pings(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        since: i64,
+    ) -> Result<Vec<i64>, sqlx::Error>;
+
+    /// Returns true if user was added to or removed from allowlist
+    async fn set_ping_allowed(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        allowed: bool,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn is_ping_allowed(&self, chat_id: ChatId, user_id: UserId) -> Result<bool, sqlx::Error>;
+
+    /// Users in allowlist of chat
+    async fn ping_allowlist(&self, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error>;
+
+    /// Remembers sent ping mentioning `targets`, forgets pings older than a
+    /// week with their targets and responses
+    async fn add_sent_ping(&self, ping: &SentPing, targets: &[UserId]) -> Result<(), sqlx::Error>;
+
+    async fn sent_ping(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Option<SentPing>, sqlx::Error>;
+
+    /// Records response of user to ping at `at` (unix time), returns true if
+    /// response was changed
+    async fn set_ping_response(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        user_id: UserId,
+        response: PingResponse,
+        at: i64,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Responses to ping, in order they were given
+    async fn ping_responses(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Vec<Responder>, sqlx::Error>;
+
+    /// Pings with `next_round` not after `now` (unix time)
+    async fn due_escalations(&self, now: i64) -> Result<Vec<SentPing>, sqlx::Error>;
+
+    /// Sets rounds left and time of next round of ping, `None` when done, if
+    /// next round is still `old_next_round`. Returns false if it is not, round
+    /// was taken by another instance sharing database then.
+    async fn update_escalation(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        old_next_round: Option<i64>,
+        rounds_left: i64,
+        next_round: Option<i64>,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Targets of ping still in chat who did not respond, without muted ones
+    async fn silent_targets(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Vec<Member>, sqlx::Error>;
+
+    /// Returns true if phrase was added to or removed from chat triggers
+    async fn set_ping_trigger(
+        &self,
+        chat_id: ChatId,
+        phrase: &str,
+        add: bool,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Phrases which ping like `/ping` when written in chat
+    async fn ping_triggers(&self, chat_id: ChatId) -> Result<Vec<String>, sqlx::Error>;
+
+    /// Returns true if user started or stopped following topic
+    async fn set_topic_follower(
+        &self,
+        chat_id: ChatId,
+        thread_id: ThreadId,
+        user_id: UserId,
+        follow: bool,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Same as [`Backend::chat_members`], only those following forum topic
+    async fn topic_followers(
+        &self,
+        chat_id: ChatId,
+        thread_id: ThreadId,
+    ) -> Result<Vec<Member>, sqlx::Error>;
+
+    /// Remembers how user wants to get pings, user may only be known from
+    /// private chat
+    async fn set_delivery(&self, user: &User, delivery: Delivery) -> Result<(), sqlx::Error>;
+
+    /// Remembers UTC offset of user in seconds, `None` for bot timezone
+    async fn set_timezone(&self, user: &User, utc_offset: Option<i32>) -> Result<(), sqlx::Error>;
+
+    /// Remembers quiet hours of user as minutes since local midnight,
+    /// `None` turns them off
+    async fn set_quiet_hours(
+        &self,
+        user: &User,
+        quiet_hours: Option<(i32, i32)>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn user_settings(&self, user_id: UserId) -> Result<UserSettings, sqlx::Error>;
+
+    /// Members of chat who changed any of their settings
+    async fn chat_user_settings(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Vec<(UserId, UserSettings)>, sqlx::Error>;
+
+    /// `(chat_id, user_id)` pairs from v0.1 schema, which only existed in SQLite
+    async fn old_members(&self) -> Result<Vec<(i64, String)>, sqlx::Error> {
+        Ok(Vec::new())
     }
+}
 
-    pub(crate) async fn old_members(&self) -> Result<Vec<v01::MemberV01>, sqlx::Error> {
-        sqlx::query_as("SELECT * FROM members")
-            .fetch_all(&self.pool)
-            .await
+/// Members are only tracked in groups and supergroups
+fn is_tracked_chat(chat: &Chat) -> bool {
+    let ChatKind::Public(ChatPublic {
+        kind: ref public_chat_kind,
+        ..
+    }) = chat.kind
+    else {
+        debug!("chat is not public");
+        return false;
+    };
+    if let PublicChatKind::Channel(_) = public_chat_kind {
+        debug!("chat is channel");
+        return false;
     }
+    true
 }
 
-#[derive(sqlx::FromRow)]
-pub(crate) struct Member {
+#[derive(Clone, sqlx::FromRow)]
+pub struct Member {
     pub(crate) user_id: String,
     pub(crate) is_bot: bool,
     pub(crate) username: Option<String>,
@@ -202,6 +333,249 @@ pub(crate) struct Member {
     pub(crate) added_to_attachment_menu: bool,
 }
 
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ScheduledPing {
+    pub(crate) id: i64,
+    pub(crate) chat_id: i64,
+    pub(crate) created_by: String,
+    /// Recurrence like `weekdays 10:00`, `None` for one-off pings
+    pub(crate) schedule: Option<String>,
+    /// Unix time
+    pub(crate) next_run: i64,
+    pub(crate) group_name: Option<String>,
+    pub(crate) text: String,
+    /// Forum topic to ping in
+    pub(crate) thread_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, sqlx::FromRow)]
+pub struct ChatSettings {
+    /// Seconds between pings in chat, 0 for no limit
+    pub(crate) ping_cooldown: i64,
+    /// Pings per user per day, 0 for no limit
+    pub(crate) daily_quota: i64,
+    #[sqlx(try_from = "String")]
+    pub(crate) ping_policy: PingPolicy,
+    /// Seconds before mentioning those who did not respond again, 0 for never
+    pub(crate) escalation_interval: i64,
+    /// How many times to mention those who did not respond again
+    pub(crate) escalation_rounds: i64,
+    /// Most mentions in one message, 0 for default
+    pub(crate) mentions_per_message: i64,
+    /// Pings of more people than this are sent only when caller confirms,
+    /// 0 for never asking
+    pub(crate) confirm_above: i64,
+}
+
+/// Who may ping the whole chat
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PingPolicy {
+    #[default]
+    All,
+    /// Chat owner and administrators
+    Admins,
+    /// Chat admins and users in allowlist
+    Allowlist,
+}
+
+impl PingPolicy {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Admins => "admins",
+            Self::Allowlist => "list",
+        }
+    }
+}
+
+impl FromStr for PingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "admins" => Ok(Self::Admins),
+            "list" => Ok(Self::Allowlist),
+            _ => Err(format!("unknown ping policy: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for PingPolicy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// How user gets pings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Delivery {
+    /// Mention in chat
+    #[default]
+    Group,
+    /// Private message with link to chat
+    Dm,
+    Both,
+}
+
+impl Delivery {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Group => "group",
+            Self::Dm => "dm",
+            Self::Both => "both",
+        }
+    }
+
+    /// True if user is mentioned in chat
+    pub(crate) fn in_group(self) -> bool {
+        matches!(self, Self::Group | Self::Both)
+    }
+
+    /// True if user gets private message
+    pub(crate) fn in_dm(self) -> bool {
+        matches!(self, Self::Dm | Self::Both)
+    }
+}
+
+impl FromStr for Delivery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "group" => Ok(Self::Group),
+            "dm" => Ok(Self::Dm),
+            "both" => Ok(Self::Both),
+            _ => Err(format!("unknown delivery: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Delivery {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Preferences of user, same in every chat
+#[derive(Debug, Clone, Copy, Default, sqlx::FromRow)]
+pub struct UserSettings {
+    #[sqlx(try_from = "String")]
+    pub(crate) delivery: Delivery,
+    /// Seconds east of UTC, bot timezone when not set
+    pub(crate) utc_offset: Option<i32>,
+    /// Minutes since local midnight quiet hours start at
+    pub(crate) quiet_from: Option<i32>,
+    /// Minutes since local midnight quiet hours end at
+    pub(crate) quiet_to: Option<i32>,
+}
+
+impl UserSettings {
+    /// Timezone of user, `default` when not set
+    pub(crate) fn timezone(&self, default: FixedOffset) -> FixedOffset {
+        self.utc_offset
+            .and_then(FixedOffset::east_opt)
+            .unwrap_or(default)
+    }
+
+    /// Quiet hours as minutes since local midnight, if set
+    pub(crate) fn quiet_hours(&self) -> Option<(i32, i32)> {
+        self.quiet_from.zip(self.quiet_to)
+    }
+
+    /// True if it is quiet hours of user at `now`
+    pub(crate) fn is_quiet(&self, now: DateTime<Utc>, default: FixedOffset) -> bool {
+        let Some((from, to)) = self.quiet_hours() else {
+            return false;
+        };
+        let local = now.with_timezone(&self.timezone(default));
+        let minute = (local.hour() * 60 + local.minute()) as i32;
+        if from <= to {
+            from <= minute && minute < to
+        } else {
+            minute >= from || minute < to
+        }
+    }
+}
+
+/// Row of [`Backend::chat_user_settings`] queries
+#[derive(sqlx::FromRow)]
+struct MemberSettings {
+    user_id: String,
+    #[sqlx(flatten)]
+    settings: UserSettings,
+}
+
+/// Ping message with acknowledgement buttons
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SentPing {
+    pub(crate) chat_id: i64,
+    /// First message of ping, the one with buttons
+    pub(crate) message_id: i32,
+    pub(crate) created_by: Option<String>,
+    /// Text of that message, without responses
+    pub(crate) text: String,
+    /// JSON of entities of `text`, None for pings sent before they were kept,
+    /// whose `text` is MarkdownV2
+    pub(crate) entities: Option<String>,
+    /// Unix time
+    pub(crate) sent_at: i64,
+    /// Repeated mentions left before reporting those who did not respond
+    pub(crate) rounds_left: i64,
+    /// Unix time of next repeated mention or report, `None` when done
+    pub(crate) next_round: Option<i64>,
+    /// Forum topic ping was sent to
+    pub(crate) thread_id: Option<i32>,
+}
+
+/// Answer to ping given with its buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingResponse {
+    Here,
+    Away,
+}
+
+impl PingResponse {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Here => "here",
+            Self::Away => "away",
+        }
+    }
+}
+
+impl FromStr for PingResponse {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "here" => Ok(Self::Here),
+            "away" => Ok(Self::Away),
+            _ => Err(format!("unknown ping response: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for PingResponse {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct Responder {
+    #[sqlx(flatten)]
+    pub(crate) member: Member,
+    #[sqlx(try_from = "String")]
+    pub(crate) response: PingResponse,
+}
+
 impl From<Member> for User {
     fn from(value: Member) -> Self {
         User {
@@ -217,6 +591,21 @@ impl From<Member> for User {
     }
 }
 
+impl From<&User> for Member {
+    fn from(value: &User) -> Self {
+        Member {
+            user_id: value.id.to_string(),
+            is_bot: value.is_bot,
+            username: value.username.clone(),
+            first_name: value.first_name.clone(),
+            last_name: value.last_name.clone(),
+            language: value.language_code.clone(),
+            is_premium: value.is_premium,
+            added_to_attachment_menu: value.added_to_attachment_menu,
+        }
+    }
+}
+
 impl Member {
     pub(crate) fn full_name(&self) -> String {
         match &self.last_name {
@@ -0,0 +1,805 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use teloxide::types::{Chat, ChatId, MessageId, ThreadId, User, UserId};
+use tracing::{debug, info};
+
+use super::{
+    is_tracked_chat, Backend, ChatSettings, Delivery, Member, PingResponse, Responder,
+    ScheduledPing, SentPing, UserSettings, PING_LOG_TTL, SENT_PING_TTL,
+};
+
+/// Storage keeping everything in process memory, lost on restart
+#[derive(Debug, Default)]
+pub(super) struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    users: HashMap<UserId, User>,
+    /// chat_id -> (title, username)
+    chats: BTreeMap<ChatId, (Option<String>, Option<String>)>,
+    chat_members: BTreeMap<(ChatId, UserId), Activity>,
+    group_members: BTreeSet<(ChatId, String, UserId)>,
+    muted_members: BTreeSet<(ChatId, UserId)>,
+    scheduled_pings: BTreeMap<i64, ScheduledPing>,
+    last_id: i64,
+    chat_settings: BTreeMap<ChatId, ChatSettings>,
+    ping_log: BTreeSet<(ChatId, UserId, i64)>,
+    ping_allowlist: BTreeSet<(ChatId, UserId)>,
+    /// (chat_id, message_id) -> ping
+    pings: BTreeMap<(ChatId, i32), SentPing>,
+    /// (chat_id, message_id, user_id) -> (response, responded_at)
+    ping_responses: BTreeMap<(ChatId, i32, UserId), (PingResponse, i64)>,
+    ping_targets: BTreeSet<(ChatId, i32, UserId)>,
+    user_settings: HashMap<UserId, UserSettings>,
+    /// (chat_id, thread_id, user_id)
+    topic_followers: BTreeSet<(ChatId, i32, UserId)>,
+    ping_triggers: BTreeSet<(ChatId, String)>,
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    last_seen: Option<i64>,
+    message_count: u64,
+}
+
+impl State {
+    fn member(&self, user_id: UserId) -> Option<Member> {
+        let user = self.users.get(&user_id)?;
+        Some(Member {
+            user_id: user.id.to_string(),
+            is_bot: user.is_bot,
+            username: user.username.clone(),
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            language: user.language_code.clone(),
+            is_premium: user.is_premium,
+            added_to_attachment_menu: user.added_to_attachment_menu,
+        })
+    }
+
+    fn is_bot(&self, user_id: UserId) -> bool {
+        self.users.get(&user_id).is_none_or(|user| user.is_bot)
+    }
+
+    /// Same as `chat_members` table joined with `users` and filtered for pinging
+    fn pingable(&self, chat_id: ChatId, user_id: UserId) -> bool {
+        self.chat_members.contains_key(&(chat_id, user_id))
+            && !self.is_bot(user_id)
+            && !self.muted_members.contains(&(chat_id, user_id))
+    }
+}
+
+impl MemoryStorage {
+    pub(super) fn new() -> Self {
+        debug!("init memory storage");
+        Self::default()
+    }
+}
+
+/// Moves keys of `set` starting with chat `from` to chat `to`, keys already
+/// present for `to` win. Returns number of moved keys.
+fn move_chat<K: Ord + Clone>(
+    set: &mut BTreeSet<K>,
+    from: ChatId,
+    to: ChatId,
+    chat_id: impl Fn(&K) -> ChatId,
+    with_chat_id: impl Fn(&K, ChatId) -> K,
+) -> u64 {
+    let old: Vec<K> = set.iter().filter(|k| chat_id(k) == from).cloned().collect();
+    let mut moved = 0;
+    for key in old {
+        set.remove(&key);
+        if set.insert(with_chat_id(&key, to)) {
+            moved += 1;
+        }
+    }
+    moved
+}
+
+#[async_trait]
+impl Backend for MemoryStorage {
+    async fn new_member(&self, chat: &Chat, user: &User) -> Result<(), sqlx::Error> {
+        if !is_tracked_chat(chat) {
+            return Ok(());
+        }
+        debug!("new member chat_id: {} user_id: {}", chat.id, user.id);
+
+        let mut state = self.state.lock().unwrap();
+        state.users.insert(user.id, user.clone());
+        state.chats.insert(
+            chat.id,
+            (
+                chat.title().map(str::to_owned),
+                chat.username().map(str::to_owned),
+            ),
+        );
+        if let Entry::Vacant(entry) = state.chat_members.entry((chat.id, user.id)) {
+            entry.insert(Activity::default());
+            info!(
+                "added member chat_id: {} user_id: {} username: {:?} first_name: {} last_name: {}",
+                chat.id,
+                user.id,
+                &user.username.as_ref().map_or("<none>", |v| v),
+                &user.first_name,
+                &user.last_name.as_ref().map_or("<none>", |v| v)
+            );
+        }
+        Ok(())
+    }
+
+    async fn member_seen(&self, chat: &Chat, user: &User, date: i64) -> Result<(), sqlx::Error> {
+        if !is_tracked_chat(chat) {
+            return Ok(());
+        }
+        self.new_member(chat, user).await?;
+        debug!("member seen chat_id: {} user_id: {}", chat.id, user.id);
+        let mut state = self.state.lock().unwrap();
+        if let Some(activity) = state.chat_members.get_mut(&(chat.id, user.id)) {
+            activity.last_seen = Some(date);
+            activity.message_count += 1;
+        }
+        Ok(())
+    }
+
+    async fn delete_member(&self, chat_id: ChatId, user_id: UserId) -> Result<(), sqlx::Error> {
+        debug!("delete member chat_id: {} user_id: {}", chat_id, user_id);
+        let mut state = self.state.lock().unwrap();
+        if state.chat_members.remove(&(chat_id, user_id)).is_some() {
+            info!("deleted member chat_id: {} user_id: {}", chat_id, user_id);
+        }
+        Ok(())
+    }
+
+    async fn chat_members(&self, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .chat_members
+            .keys()
+            .filter(|&&(c, u)| c == chat_id && state.pingable(c, u))
+            .filter_map(|&(_, u)| state.member(u))
+            .collect())
+    }
+
+    async fn active_members(
+        &self,
+        chat_id: ChatId,
+        since: i64,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .chat_members
+            .iter()
+            .filter(|&(&(c, u), activity)| {
+                c == chat_id
+                    && activity.last_seen.is_some_and(|seen| seen >= since)
+                    && state.pingable(c, u)
+            })
+            .filter_map(|(&(_, u), _)| state.member(u))
+            .collect())
+    }
+
+    async fn chat_members_count(&self, chat_id: ChatId) -> Result<u64, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .chat_members
+            .keys()
+            .filter(|&&(c, u)| c == chat_id && state.pingable(c, u))
+            .count() as u64)
+    }
+
+    async fn chats_with_counts(&self) -> Result<Vec<(i64, Option<String>, u64)>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .chats
+            .iter()
+            .filter_map(|(&chat_id, (title, _))| {
+                let count = state
+                    .chat_members
+                    .keys()
+                    .filter(|&&(c, u)| c == chat_id && !state.is_bot(u))
+                    .count() as u64;
+                (count > 0).then(|| (chat_id.0, title.clone(), count))
+            })
+            .collect())
+    }
+
+    async fn tracked_chats(&self) -> Result<Vec<ChatId>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let chats: BTreeSet<ChatId> = state.chat_members.keys().map(|&(c, _)| c).collect();
+        Ok(chats.into_iter().collect())
+    }
+
+    async fn chat_member_ids(&self, chat_id: ChatId) -> Result<Vec<UserId>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .chat_members
+            .keys()
+            .filter(|&&(c, _)| c == chat_id)
+            .map(|&(_, u)| u)
+            .collect())
+    }
+
+    async fn join_group(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        group: &str,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "join group chat_id: {} user_id: {} group: {}",
+            chat_id, user_id, group
+        );
+        let mut state = self.state.lock().unwrap();
+        Ok(state
+            .group_members
+            .insert((chat_id, group.to_owned(), user_id)))
+    }
+
+    async fn leave_group(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        group: &str,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "leave group chat_id: {} user_id: {} group: {}",
+            chat_id, user_id, group
+        );
+        let mut state = self.state.lock().unwrap();
+        Ok(state
+            .group_members
+            .remove(&(chat_id, group.to_owned(), user_id)))
+    }
+
+    async fn group_members(
+        &self,
+        chat_id: ChatId,
+        group: &str,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .group_members
+            .iter()
+            .filter(|(c, g, u)| *c == chat_id && g == group && state.pingable(*c, *u))
+            .filter_map(|&(_, _, u)| state.member(u))
+            .collect())
+    }
+
+    async fn chat_groups(&self, chat_id: ChatId) -> Result<Vec<(String, u64)>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut groups: BTreeMap<String, u64> = BTreeMap::new();
+        for (_, group, _) in state
+            .group_members
+            .iter()
+            .filter(|(c, _, u)| *c == chat_id && state.chat_members.contains_key(&(*c, *u)))
+        {
+            *groups.entry(group.clone()).or_default() += 1;
+        }
+        Ok(groups.into_iter().collect())
+    }
+
+    async fn set_muted(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        muted: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set muted chat_id: {} user_id: {} muted: {}",
+            chat_id, user_id, muted
+        );
+        let mut state = self.state.lock().unwrap();
+        Ok(if muted {
+            state.muted_members.insert((chat_id, user_id))
+        } else {
+            state.muted_members.remove(&(chat_id, user_id))
+        })
+    }
+
+    async fn migrate_chat(&self, from: ChatId, to: ChatId) -> Result<(), sqlx::Error> {
+        debug!("migrate chat from: {} to: {}", from, to);
+        let mut state = self.state.lock().unwrap();
+        let mut moved = 0;
+        if let Some(chat) = state.chats.remove(&from) {
+            if let Entry::Vacant(entry) = state.chats.entry(to) {
+                entry.insert(chat);
+                moved += 1;
+            }
+        }
+        let members: Vec<_> = state
+            .chat_members
+            .keys()
+            .filter(|&&(c, _)| c == from)
+            .copied()
+            .collect();
+        for key in members {
+            let activity = state.chat_members.remove(&key).unwrap_or_default();
+            if let Entry::Vacant(entry) = state.chat_members.entry((to, key.1)) {
+                entry.insert(activity);
+                moved += 1;
+            }
+        }
+        moved += move_chat(
+            &mut state.group_members,
+            from,
+            to,
+            |k| k.0,
+            |k, c| (c, k.1.clone(), k.2),
+        );
+        moved += move_chat(&mut state.muted_members, from, to, |k| k.0, |k, c| (c, k.1));
+        for ping in state.scheduled_pings.values_mut() {
+            if ping.chat_id == from.0 {
+                ping.chat_id = to.0;
+                moved += 1;
+            }
+        }
+        if let Some(settings) = state.chat_settings.remove(&from) {
+            if let Entry::Vacant(entry) = state.chat_settings.entry(to) {
+                entry.insert(settings);
+                moved += 1;
+            }
+        }
+        moved += move_chat(&mut state.ping_log, from, to, |k| k.0, |k, c| (c, k.1, k.2));
+        moved += move_chat(
+            &mut state.ping_allowlist,
+            from,
+            to,
+            |k| k.0,
+            |k, c| (c, k.1),
+        );
+        moved += move_chat(
+            &mut state.ping_triggers,
+            from,
+            to,
+            |k| k.0,
+            |k, c| (c, k.1.clone()),
+        );
+        // message ids of old chat mean nothing in new one
+        state.pings.retain(|k, _| k.0 != from);
+        state.ping_responses.retain(|k, _| k.0 != from);
+        state.ping_targets.retain(|k| k.0 != from);
+        moved += move_chat(
+            &mut state.topic_followers,
+            from,
+            to,
+            |k| k.0,
+            |k, c| (c, k.1, k.2),
+        );
+        if moved > 0 {
+            info!("migrated chat from: {} to: {} rows: {}", from, to, moved);
+        }
+        Ok(())
+    }
+
+    async fn add_scheduled_ping(&self, ping: &ScheduledPing) -> Result<i64, sqlx::Error> {
+        debug!(
+            "add scheduled ping chat_id: {} schedule: {:?} next_run: {}",
+            ping.chat_id, ping.schedule, ping.next_run
+        );
+        let mut state = self.state.lock().unwrap();
+        state.last_id += 1;
+        let id = state.last_id;
+        state
+            .scheduled_pings
+            .insert(id, ScheduledPing { id, ..ping.clone() });
+        Ok(id)
+    }
+
+    async fn chat_scheduled_pings(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Vec<ScheduledPing>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut pings: Vec<ScheduledPing> = state
+            .scheduled_pings
+            .values()
+            .filter(|ping| ping.chat_id == chat_id.0)
+            .cloned()
+            .collect();
+        pings.sort_by_key(|ping| ping.next_run);
+        Ok(pings)
+    }
+
+    async fn due_scheduled_pings(&self, now: i64) -> Result<Vec<ScheduledPing>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut pings: Vec<ScheduledPing> = state
+            .scheduled_pings
+            .values()
+            .filter(|ping| ping.next_run <= now)
+            .cloned()
+            .collect();
+        pings.sort_by_key(|ping| ping.next_run);
+        Ok(pings)
+    }
+
+    async fn reschedule_ping(
+        &self,
+        id: i64,
+        old_next_run: i64,
+        next_run: i64,
+    ) -> Result<bool, sqlx::Error> {
+        debug!("reschedule ping id: {} next_run: {}", id, next_run);
+        let mut state = self.state.lock().unwrap();
+        match state.scheduled_pings.get_mut(&id) {
+            Some(ping) if ping.next_run == old_next_run => {
+                ping.next_run = next_run;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_scheduled_ping(&self, chat_id: ChatId, id: i64) -> Result<bool, sqlx::Error> {
+        debug!("delete scheduled ping chat_id: {} id: {}", chat_id, id);
+        let mut state = self.state.lock().unwrap();
+        if state
+            .scheduled_pings
+            .get(&id)
+            .is_some_and(|ping| ping.chat_id == chat_id.0)
+        {
+            state.scheduled_pings.remove(&id);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    async fn chat_settings(&self, chat_id: ChatId) -> Result<ChatSettings, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .chat_settings
+            .get(&chat_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn save_chat_settings(
+        &self,
+        chat_id: ChatId,
+        settings: &ChatSettings,
+    ) -> Result<(), sqlx::Error> {
+        debug!("save chat settings chat_id: {} {:?}", chat_id, settings);
+        let mut state = self.state.lock().unwrap();
+        state.chat_settings.insert(chat_id, settings.clone());
+        Ok(())
+    }
+
+    async fn log_ping(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        sent_at: i64,
+    ) -> Result<(), sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        state.ping_log.insert((chat_id, user_id, sent_at));
+        state
+            .ping_log
+            .retain(|&(_, _, at)| at >= sent_at - PING_LOG_TTL);
+        Ok(())
+    }
+
+    async fn last_ping(&self, chat_id: ChatId) -> Result<Option<i64>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .ping_log
+            .iter()
+            .filter(|&&(c, _, _)| c == chat_id)
+            .map(|&(_, _, at)| at)
+            .max())
+    }
+
+    async fn user_pings(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        since: i64,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut pings: Vec<i64> = state
+            .ping_log
+            .iter()
+            .filter(|&&(c, u, at)| c == chat_id && u == user_id && at >= since)
+            .map(|&(_, _, at)| at)
+            .collect();
+        pings.sort_unstable();
+        Ok(pings)
+    }
+
+    async fn set_ping_allowed(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        allowed: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set ping allowed chat_id: {} user_id: {} allowed: {}",
+            chat_id, user_id, allowed
+        );
+        let mut state = self.state.lock().unwrap();
+        Ok(if allowed {
+            state.ping_allowlist.insert((chat_id, user_id))
+        } else {
+            state.ping_allowlist.remove(&(chat_id, user_id))
+        })
+    }
+
+    async fn is_ping_allowed(&self, chat_id: ChatId, user_id: UserId) -> Result<bool, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.ping_allowlist.contains(&(chat_id, user_id)))
+    }
+
+    async fn ping_allowlist(&self, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .ping_allowlist
+            .iter()
+            .filter(|&&(c, _)| c == chat_id)
+            .filter_map(|&(_, u)| state.member(u))
+            .collect())
+    }
+
+    async fn add_sent_ping(&self, ping: &SentPing, targets: &[UserId]) -> Result<(), sqlx::Error> {
+        debug!(
+            "add sent ping chat_id: {} message_id: {} targets: {}",
+            ping.chat_id,
+            ping.message_id,
+            targets.len()
+        );
+        let mut state = self.state.lock().unwrap();
+        let chat_id = ChatId(ping.chat_id);
+        state.pings.insert((chat_id, ping.message_id), ping.clone());
+        for &user_id in targets {
+            state
+                .ping_targets
+                .insert((chat_id, ping.message_id, user_id));
+        }
+        state
+            .pings
+            .retain(|_, old| old.sent_at >= ping.sent_at - SENT_PING_TTL);
+        let State {
+            pings,
+            ping_responses,
+            ping_targets,
+            ..
+        } = &mut *state;
+        ping_responses.retain(|&(c, m, _), _| pings.contains_key(&(c, m)));
+        ping_targets.retain(|&(c, m, _)| pings.contains_key(&(c, m)));
+        Ok(())
+    }
+
+    async fn sent_ping(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Option<SentPing>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.pings.get(&(chat_id, message_id.0)).cloned())
+    }
+
+    async fn set_ping_response(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        user_id: UserId,
+        response: PingResponse,
+        at: i64,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set ping response chat_id: {} message_id: {} user_id: {} response: {:?}",
+            chat_id, message_id, user_id, response
+        );
+        let mut state = self.state.lock().unwrap();
+        match state.ping_responses.entry((chat_id, message_id.0, user_id)) {
+            Entry::Occupied(entry) if entry.get().0 == response => Ok(false),
+            Entry::Occupied(mut entry) => {
+                entry.insert((response, at));
+                Ok(true)
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((response, at));
+                Ok(true)
+            }
+        }
+    }
+
+    async fn ping_responses(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Vec<Responder>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut responses: Vec<_> = state
+            .ping_responses
+            .iter()
+            .filter(|&(&(c, m, _), _)| c == chat_id && m == message_id.0)
+            .collect();
+        responses.sort_by_key(|(_, &(_, at))| at);
+        Ok(responses
+            .into_iter()
+            .filter_map(|(&(_, _, u), &(response, _))| {
+                state.member(u).map(|member| Responder { member, response })
+            })
+            .collect())
+    }
+
+    async fn due_escalations(&self, now: i64) -> Result<Vec<SentPing>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut pings: Vec<SentPing> = state
+            .pings
+            .values()
+            .filter(|ping| ping.next_round.is_some_and(|next| next <= now))
+            .cloned()
+            .collect();
+        pings.sort_by_key(|ping| ping.next_round);
+        Ok(pings)
+    }
+
+    async fn update_escalation(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        old_next_round: Option<i64>,
+        rounds_left: i64,
+        next_round: Option<i64>,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "update escalation chat_id: {} message_id: {} rounds_left: {} next_round: {:?}",
+            chat_id, message_id, rounds_left, next_round
+        );
+        let mut state = self.state.lock().unwrap();
+        match state.pings.get_mut(&(chat_id, message_id.0)) {
+            Some(ping) if ping.next_round == old_next_round => {
+                ping.rounds_left = rounds_left;
+                ping.next_round = next_round;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn silent_targets(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .ping_targets
+            .iter()
+            .filter(|&&(c, m, u)| {
+                c == chat_id
+                    && m == message_id.0
+                    && state.chat_members.contains_key(&(c, u))
+                    && !state.muted_members.contains(&(c, u))
+                    && !state.ping_responses.contains_key(&(c, m, u))
+            })
+            .filter_map(|&(_, _, u)| state.member(u))
+            .collect())
+    }
+
+    async fn set_ping_trigger(
+        &self,
+        chat_id: ChatId,
+        phrase: &str,
+        add: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set ping trigger chat_id: {} phrase: {:?} add: {}",
+            chat_id, phrase, add
+        );
+        let mut state = self.state.lock().unwrap();
+        let key = (chat_id, phrase.to_owned());
+        Ok(if add {
+            state.ping_triggers.insert(key)
+        } else {
+            state.ping_triggers.remove(&key)
+        })
+    }
+
+    async fn ping_triggers(&self, chat_id: ChatId) -> Result<Vec<String>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .ping_triggers
+            .iter()
+            .filter(|(c, _)| *c == chat_id)
+            .map(|(_, phrase)| phrase.clone())
+            .collect())
+    }
+
+    async fn set_topic_follower(
+        &self,
+        chat_id: ChatId,
+        thread_id: ThreadId,
+        user_id: UserId,
+        follow: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set topic follower chat_id: {} thread_id: {} user_id: {} follow: {}",
+            chat_id, thread_id, user_id, follow
+        );
+        let mut state = self.state.lock().unwrap();
+        let key = (chat_id, thread_id.0 .0, user_id);
+        Ok(if follow {
+            state.topic_followers.insert(key)
+        } else {
+            state.topic_followers.remove(&key)
+        })
+    }
+
+    async fn topic_followers(
+        &self,
+        chat_id: ChatId,
+        thread_id: ThreadId,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .topic_followers
+            .iter()
+            .filter(|&&(c, t, u)| c == chat_id && t == thread_id.0 .0 && state.pingable(c, u))
+            .filter_map(|&(_, _, u)| state.member(u))
+            .collect())
+    }
+
+    async fn set_delivery(&self, user: &User, delivery: Delivery) -> Result<(), sqlx::Error> {
+        debug!("set delivery user_id: {} delivery: {:?}", user.id, delivery);
+        let mut state = self.state.lock().unwrap();
+        state.users.insert(user.id, user.clone());
+        state.user_settings.entry(user.id).or_default().delivery = delivery;
+        Ok(())
+    }
+
+    async fn set_timezone(&self, user: &User, utc_offset: Option<i32>) -> Result<(), sqlx::Error> {
+        debug!(
+            "set timezone user_id: {} utc_offset: {:?}",
+            user.id, utc_offset
+        );
+        let mut state = self.state.lock().unwrap();
+        state.users.insert(user.id, user.clone());
+        state.user_settings.entry(user.id).or_default().utc_offset = utc_offset;
+        Ok(())
+    }
+
+    async fn set_quiet_hours(
+        &self,
+        user: &User,
+        quiet_hours: Option<(i32, i32)>,
+    ) -> Result<(), sqlx::Error> {
+        debug!(
+            "set quiet hours user_id: {} quiet_hours: {:?}",
+            user.id, quiet_hours
+        );
+        let mut state = self.state.lock().unwrap();
+        state.users.insert(user.id, user.clone());
+        let settings = state.user_settings.entry(user.id).or_default();
+        settings.quiet_from = quiet_hours.map(|(from, _)| from);
+        settings.quiet_to = quiet_hours.map(|(_, to)| to);
+        Ok(())
+    }
+
+    async fn user_settings(&self, user_id: UserId) -> Result<UserSettings, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .user_settings
+            .get(&user_id)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn chat_user_settings(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Vec<(UserId, UserSettings)>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .user_settings
+            .iter()
+            .filter(|&(&user_id, _)| state.chat_members.contains_key(&(chat_id, user_id)))
+            .map(|(&user_id, &settings)| (user_id, settings))
+            .collect())
+    }
+}
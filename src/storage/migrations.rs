@@ -0,0 +1,304 @@
+/// Schema migrations, applied in order, each one in its own transaction.
+/// Version of schema is the number of applied migrations. Never change
+/// migrations which are already released, append new ones instead.
+pub(super) const SQLITE: &[&[&str]] = &[
+    // 1: initial schema, tables may already exist in databases created before
+    // schema versioning
+    &[
+        "CREATE TABLE IF NOT EXISTS chat_members (
+            chat_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS users (
+            user_id TEXT NOT NULL,
+            is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+            username TEXT,
+            first_name TEXT NOT NULL,
+            last_name TEXT,
+            language TEXT,
+            is_premium BOOLEAN NOT NULL DEFAULT FALSE,
+            added_to_attachment_menu BOOLEAN NOT NULL DEFAULT FALSE,
+            PRIMARY KEY (user_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS chats (
+            chat_id INTEGER NOT NULL,
+            title TEXT,
+            username TEXT,
+            PRIMARY KEY (chat_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS group_members (
+            chat_id INTEGER NOT NULL,
+            group_name TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, group_name, user_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS muted_members (
+            chat_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        )",
+    ],
+    // 2: activity of members
+    &[
+        "ALTER TABLE chat_members ADD COLUMN last_seen INTEGER",
+        "ALTER TABLE chat_members ADD COLUMN message_count INTEGER NOT NULL DEFAULT 0",
+    ],
+    // 3: scheduled pings
+    &[
+        "CREATE TABLE scheduled_pings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            chat_id INTEGER NOT NULL,
+            created_by TEXT NOT NULL,
+            schedule TEXT,
+            next_run INTEGER NOT NULL,
+            group_name TEXT,
+            text TEXT NOT NULL
+        )",
+        "CREATE INDEX scheduled_pings_next_run ON scheduled_pings (next_run)",
+    ],
+    // 4: ping limits
+    &[
+        "CREATE TABLE chat_settings (
+            chat_id INTEGER NOT NULL,
+            ping_cooldown INTEGER NOT NULL DEFAULT 0,
+            daily_quota INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (chat_id)
+        )",
+        "CREATE TABLE ping_log (
+            chat_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            sent_at INTEGER NOT NULL,
+            PRIMARY KEY (chat_id, user_id, sent_at)
+        )",
+    ],
+    // 5: ping policy
+    &[
+        "ALTER TABLE chat_settings ADD COLUMN ping_policy TEXT NOT NULL DEFAULT 'all'",
+        "CREATE TABLE ping_allowlist (
+            chat_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        )",
+    ],
+    // 6: ping acknowledgements
+    &[
+        "CREATE TABLE pings (
+            chat_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            created_by TEXT,
+            text TEXT NOT NULL,
+            sent_at INTEGER NOT NULL,
+            PRIMARY KEY (chat_id, message_id)
+        )",
+        "CREATE TABLE ping_responses (
+            chat_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            response TEXT NOT NULL,
+            responded_at INTEGER NOT NULL,
+            PRIMARY KEY (chat_id, message_id, user_id)
+        )",
+    ],
+    // 7: escalation of pings
+    &[
+        "ALTER TABLE chat_settings ADD COLUMN escalation_interval INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE chat_settings ADD COLUMN escalation_rounds INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE pings ADD COLUMN rounds_left INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE pings ADD COLUMN next_round INTEGER",
+        "CREATE TABLE ping_targets (
+            chat_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, message_id, user_id)
+        )",
+        "CREATE INDEX pings_next_round ON pings (next_round)",
+    ],
+    // 8: delivery preferences
+    &["CREATE TABLE user_settings (
+            user_id TEXT NOT NULL,
+            delivery TEXT NOT NULL DEFAULT 'group',
+            PRIMARY KEY (user_id)
+        )"],
+    // 9: forum topics
+    &[
+        "ALTER TABLE scheduled_pings ADD COLUMN thread_id INTEGER",
+        "ALTER TABLE pings ADD COLUMN thread_id INTEGER",
+        "CREATE TABLE topic_followers (
+            chat_id INTEGER NOT NULL,
+            thread_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, thread_id, user_id)
+        )",
+    ],
+    // 10: timezone and quiet hours of users
+    &[
+        "ALTER TABLE user_settings ADD COLUMN utc_offset INTEGER",
+        "ALTER TABLE user_settings ADD COLUMN quiet_from INTEGER",
+        "ALTER TABLE user_settings ADD COLUMN quiet_to INTEGER",
+    ],
+    // 11: text triggers
+    &["CREATE TABLE ping_triggers (
+            chat_id INTEGER NOT NULL,
+            phrase TEXT NOT NULL,
+            PRIMARY KEY (chat_id, phrase)
+        )"],
+    // 12: pings sent as text with entities, NULL for MarkdownV2 ones sent
+    // before
+    &["ALTER TABLE pings ADD COLUMN entities TEXT"],
+    // 13: mentions per message
+    &["ALTER TABLE chat_settings ADD COLUMN mentions_per_message INTEGER NOT NULL DEFAULT 0"],
+    // 14: confirmation of large pings
+    &["ALTER TABLE chat_settings ADD COLUMN confirm_above INTEGER NOT NULL DEFAULT 0"],
+];
+
+/// Same as [`SQLITE`], for PostgreSQL
+pub(super) const POSTGRES: &[&[&str]] = &[
+    // 1: initial schema
+    &[
+        "CREATE TABLE IF NOT EXISTS chat_members (
+            chat_id BIGINT NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS users (
+            user_id TEXT NOT NULL,
+            is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+            username TEXT,
+            first_name TEXT NOT NULL,
+            last_name TEXT,
+            language TEXT,
+            is_premium BOOLEAN NOT NULL DEFAULT FALSE,
+            added_to_attachment_menu BOOLEAN NOT NULL DEFAULT FALSE,
+            PRIMARY KEY (user_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS chats (
+            chat_id BIGINT NOT NULL,
+            title TEXT,
+            username TEXT,
+            PRIMARY KEY (chat_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS group_members (
+            chat_id BIGINT NOT NULL,
+            group_name TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, group_name, user_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS muted_members (
+            chat_id BIGINT NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        )",
+    ],
+    // 2: activity of members
+    &[
+        "ALTER TABLE chat_members ADD COLUMN last_seen BIGINT",
+        "ALTER TABLE chat_members ADD COLUMN message_count BIGINT NOT NULL DEFAULT 0",
+    ],
+    // 3: scheduled pings
+    &[
+        "CREATE TABLE scheduled_pings (
+            id BIGSERIAL PRIMARY KEY,
+            chat_id BIGINT NOT NULL,
+            created_by TEXT NOT NULL,
+            schedule TEXT,
+            next_run BIGINT NOT NULL,
+            group_name TEXT,
+            text TEXT NOT NULL
+        )",
+        "CREATE INDEX scheduled_pings_next_run ON scheduled_pings (next_run)",
+    ],
+    // 4: ping limits
+    &[
+        "CREATE TABLE chat_settings (
+            chat_id BIGINT NOT NULL,
+            ping_cooldown BIGINT NOT NULL DEFAULT 0,
+            daily_quota BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (chat_id)
+        )",
+        "CREATE TABLE ping_log (
+            chat_id BIGINT NOT NULL,
+            user_id TEXT NOT NULL,
+            sent_at BIGINT NOT NULL,
+            PRIMARY KEY (chat_id, user_id, sent_at)
+        )",
+    ],
+    // 5: ping policy
+    &[
+        "ALTER TABLE chat_settings ADD COLUMN ping_policy TEXT NOT NULL DEFAULT 'all'",
+        "CREATE TABLE ping_allowlist (
+            chat_id BIGINT NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        )",
+    ],
+    // 6: ping acknowledgements
+    &[
+        "CREATE TABLE pings (
+            chat_id BIGINT NOT NULL,
+            message_id INTEGER NOT NULL,
+            created_by TEXT,
+            text TEXT NOT NULL,
+            sent_at BIGINT NOT NULL,
+            PRIMARY KEY (chat_id, message_id)
+        )",
+        "CREATE TABLE ping_responses (
+            chat_id BIGINT NOT NULL,
+            message_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            response TEXT NOT NULL,
+            responded_at BIGINT NOT NULL,
+            PRIMARY KEY (chat_id, message_id, user_id)
+        )",
+    ],
+    // 7: escalation of pings
+    &[
+        "ALTER TABLE chat_settings ADD COLUMN escalation_interval BIGINT NOT NULL DEFAULT 0",
+        "ALTER TABLE chat_settings ADD COLUMN escalation_rounds BIGINT NOT NULL DEFAULT 0",
+        "ALTER TABLE pings ADD COLUMN rounds_left BIGINT NOT NULL DEFAULT 0",
+        "ALTER TABLE pings ADD COLUMN next_round BIGINT",
+        "CREATE TABLE ping_targets (
+            chat_id BIGINT NOT NULL,
+            message_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, message_id, user_id)
+        )",
+        "CREATE INDEX pings_next_round ON pings (next_round)",
+    ],
+    // 8: delivery preferences
+    &["CREATE TABLE user_settings (
+            user_id TEXT NOT NULL,
+            delivery TEXT NOT NULL DEFAULT 'group',
+            PRIMARY KEY (user_id)
+        )"],
+    // 9: forum topics
+    &[
+        "ALTER TABLE scheduled_pings ADD COLUMN thread_id INTEGER",
+        "ALTER TABLE pings ADD COLUMN thread_id INTEGER",
+        "CREATE TABLE topic_followers (
+            chat_id BIGINT NOT NULL,
+            thread_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY (chat_id, thread_id, user_id)
+        )",
+    ],
+    // 10: timezone and quiet hours of users
+    &[
+        "ALTER TABLE user_settings ADD COLUMN utc_offset INTEGER",
+        "ALTER TABLE user_settings ADD COLUMN quiet_from INTEGER",
+        "ALTER TABLE user_settings ADD COLUMN quiet_to INTEGER",
+    ],
+    // 11: text triggers
+    &["CREATE TABLE ping_triggers (
+            chat_id BIGINT NOT NULL,
+            phrase TEXT NOT NULL,
+            PRIMARY KEY (chat_id, phrase)
+        )"],
+    // 12: pings sent as text with entities, NULL for MarkdownV2 ones sent
+    // before
+    &["ALTER TABLE pings ADD COLUMN entities TEXT"],
+    // 13: mentions per message
+    &["ALTER TABLE chat_settings ADD COLUMN mentions_per_message BIGINT NOT NULL DEFAULT 0"],
+    // 14: confirmation of large pings
+    &["ALTER TABLE chat_settings ADD COLUMN confirm_above BIGINT NOT NULL DEFAULT 0"],
+];
@@ -0,0 +1,875 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use sqlx::{sqlite::SqliteConnectOptions, Row, SqlitePool};
+use teloxide::types::{Chat, ChatId, MessageId, ThreadId, User, UserId};
+use tracing::{debug, info, trace};
+
+use super::{
+    is_tracked_chat, migrations, v01, Backend, ChatSettings, Delivery, Member, MemberSettings,
+    PingResponse, Responder, ScheduledPing, SentPing, UserSettings, CHAT_TABLES, MESSAGE_TABLES,
+    PING_LOG_TTL, SENT_PING_TTL,
+};
+
+#[derive(Debug, Clone)]
+pub(super) struct SqliteStorage {
+    pool: sqlx::Pool<sqlx::Sqlite>,
+}
+
+impl SqliteStorage {
+    pub(super) async fn init(file: &Path) -> Result<Self, sqlx::Error> {
+        debug!("init sqlite storage");
+        let opts = SqliteConnectOptions::new()
+            .filename(file)
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(opts).await?;
+        Self::migrate(&pool).await?;
+        Ok(Self { pool })
+    }
+
+    /// Brings schema up to date, applying migrations not applied yet
+    async fn migrate(pool: &sqlx::Pool<sqlx::Sqlite>) -> Result<(), sqlx::Error> {
+        sqlx::query("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")
+            .execute(pool)
+            .await?;
+        let version: i64 = sqlx::query("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+            .fetch_one(pool)
+            .await?
+            .get(0);
+        trace!("schema version: {}", version);
+
+        for (version, statements) in migrations::SQLITE.iter().enumerate().skip(version as usize) {
+            let version = version as i64 + 1;
+            debug!("applying migration {}", version);
+            let mut tx = pool.begin().await?;
+            for statement in *statements {
+                sqlx::query(statement).execute(&mut *tx).await?;
+            }
+            sqlx::query("DELETE FROM schema_version")
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("INSERT INTO schema_version (version) VALUES (?)")
+                .bind(version)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            info!("applied migration {}", version);
+        }
+        Ok(())
+    }
+
+    /// Creates or updates user
+    async fn save_user(&self, user: &User) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT
+            INTO users (
+                user_id, is_bot, username, first_name,
+                last_name, language, is_premium, added_to_attachment_menu
+                )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (user_id)
+            DO UPDATE SET
+                is_bot = EXCLUDED.is_bot,
+                username = EXCLUDED.username,
+                first_name = EXCLUDED.first_name,
+                last_name = EXCLUDED.last_name,
+                language = EXCLUDED.language,
+                is_premium = EXCLUDED.is_premium,
+                added_to_attachment_menu = EXCLUDED.added_to_attachment_menu",
+        )
+        .bind(user.id.to_string())
+        .bind(user.is_bot)
+        .bind(&user.username)
+        .bind(&user.first_name)
+        .bind(&user.last_name)
+        .bind(&user.language_code)
+        .bind(user.is_premium)
+        .bind(user.added_to_attachment_menu)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Backend for SqliteStorage {
+    async fn new_member(&self, chat: &Chat, user: &User) -> Result<(), sqlx::Error> {
+        if !is_tracked_chat(chat) {
+            return Ok(());
+        }
+        debug!("new member chat_id: {} user_id: {}", chat.id, user.id);
+
+        self.save_user(user).await?;
+        let user_id = user.id.to_string();
+
+        sqlx::query(
+            "INSERT
+            INTO chats (chat_id, title, username)
+            VALUES (?, ?, ?)
+            ON CONFLICT (chat_id)
+            DO UPDATE SET
+                title = EXCLUDED.title,
+                username = EXCLUDED.username",
+        )
+        .bind(chat.id.0)
+        .bind(chat.title())
+        .bind(chat.username())
+        .execute(&self.pool)
+        .await?;
+
+        let result =
+            sqlx::query("INSERT OR IGNORE INTO chat_members (chat_id, user_id) VALUES (?, ?)")
+                .bind(chat.id.0)
+                .bind(&user_id)
+                .execute(&self.pool)
+                .await?;
+        if result.rows_affected() > 0 {
+            info!(
+                "added member chat_id: {} user_id: {} username: {:?} first_name: {} last_name: {}",
+                chat.id,
+                user.id,
+                &user.username.as_ref().map_or("<none>", |v| v),
+                &user.first_name,
+                &user.last_name.as_ref().map_or("<none>", |v| v)
+            );
+        }
+        Ok(())
+    }
+
+    async fn member_seen(&self, chat: &Chat, user: &User, date: i64) -> Result<(), sqlx::Error> {
+        if !is_tracked_chat(chat) {
+            return Ok(());
+        }
+        self.new_member(chat, user).await?;
+        debug!("member seen chat_id: {} user_id: {}", chat.id, user.id);
+        sqlx::query(
+            "UPDATE chat_members SET last_seen = ?, message_count = message_count + 1 WHERE chat_id = ? AND user_id = ?",
+        )
+        .bind(date)
+        .bind(chat.id.0)
+        .bind(user.id.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_member(&self, chat_id: ChatId, user_id: UserId) -> Result<(), sqlx::Error> {
+        debug!("delete member chat_id: {} user_id: {}", chat_id, user_id);
+        let result = sqlx::query("DELETE FROM chat_members WHERE chat_id = ? AND user_id = ?")
+            .bind(chat_id.0)
+            .bind(user_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            info!("deleted member chat_id: {} user_id: {}", chat_id, user_id);
+        }
+        Ok(())
+    }
+
+    async fn chat_members(&self, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error> {
+        sqlx::query_as("SELECT u.* FROM chat_members cm JOIN users u ON cm.user_id = u.user_id WHERE cm.chat_id = ? AND NOT(u.is_bot) AND NOT EXISTS (SELECT 1 FROM muted_members mm WHERE mm.chat_id = cm.chat_id AND mm.user_id = cm.user_id)")
+            .bind(chat_id.0)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn active_members(
+        &self,
+        chat_id: ChatId,
+        since: i64,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        sqlx::query_as("SELECT u.* FROM chat_members cm JOIN users u ON cm.user_id = u.user_id WHERE cm.chat_id = ? AND cm.last_seen >= ? AND NOT(u.is_bot) AND NOT EXISTS (SELECT 1 FROM muted_members mm WHERE mm.chat_id = cm.chat_id AND mm.user_id = cm.user_id)")
+            .bind(chat_id.0)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn chat_members_count(&self, chat_id: ChatId) -> Result<u64, sqlx::Error> {
+        sqlx::query("SELECT COUNT(*) FROM chat_members cm JOIN users u ON cm.user_id = u.user_id WHERE cm.chat_id = ? AND NOT(u.is_bot) AND NOT EXISTS (SELECT 1 FROM muted_members mm WHERE mm.chat_id = cm.chat_id AND mm.user_id = cm.user_id)")
+            .bind(chat_id.0)
+            .fetch_one(&self.pool)
+            .await
+            .map(|row| row.get(0))
+    }
+
+    async fn chats_with_counts(&self) -> Result<Vec<(i64, Option<String>, u64)>, sqlx::Error> {
+        sqlx::query_as("SELECT c.chat_id, c.title, COUNT(cm.user_id) FROM chats c JOIN chat_members cm ON c.chat_id = cm.chat_id LEFT JOIN users u ON cm.user_id = u.user_id WHERE NOT u.is_bot GROUP BY c.chat_id, c.title ORDER BY c.chat_id")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn tracked_chats(&self) -> Result<Vec<ChatId>, sqlx::Error> {
+        sqlx::query("SELECT DISTINCT chat_id FROM chat_members ORDER BY chat_id")
+            .fetch_all(&self.pool)
+            .await
+            .map(|rows| rows.into_iter().map(|row| ChatId(row.get(0))).collect())
+    }
+
+    async fn chat_member_ids(&self, chat_id: ChatId) -> Result<Vec<UserId>, sqlx::Error> {
+        let rows = sqlx::query("SELECT user_id FROM chat_members WHERE chat_id = ?")
+            .bind(chat_id.0)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| row.get::<String, _>(0).parse().ok().map(UserId))
+            .collect())
+    }
+
+    async fn join_group(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        group: &str,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "join group chat_id: {} user_id: {} group: {}",
+            chat_id, user_id, group
+        );
+        let result = sqlx::query(
+            "INSERT OR IGNORE INTO group_members (chat_id, group_name, user_id) VALUES (?, ?, ?)",
+        )
+        .bind(chat_id.0)
+        .bind(group)
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn leave_group(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        group: &str,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "leave group chat_id: {} user_id: {} group: {}",
+            chat_id, user_id, group
+        );
+        let result = sqlx::query(
+            "DELETE FROM group_members WHERE chat_id = ? AND group_name = ? AND user_id = ?",
+        )
+        .bind(chat_id.0)
+        .bind(group)
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn group_members(
+        &self,
+        chat_id: ChatId,
+        group: &str,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        sqlx::query_as("SELECT u.* FROM group_members gm JOIN chat_members cm ON gm.chat_id = cm.chat_id AND gm.user_id = cm.user_id JOIN users u ON gm.user_id = u.user_id WHERE gm.chat_id = ? AND gm.group_name = ? AND NOT(u.is_bot) AND NOT EXISTS (SELECT 1 FROM muted_members mm WHERE mm.chat_id = gm.chat_id AND mm.user_id = gm.user_id)")
+            .bind(chat_id.0)
+            .bind(group)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn chat_groups(&self, chat_id: ChatId) -> Result<Vec<(String, u64)>, sqlx::Error> {
+        sqlx::query_as("SELECT gm.group_name, COUNT(gm.user_id) FROM group_members gm JOIN chat_members cm ON gm.chat_id = cm.chat_id AND gm.user_id = cm.user_id WHERE gm.chat_id = ? GROUP BY gm.group_name ORDER BY gm.group_name")
+            .bind(chat_id.0)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn set_muted(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        muted: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set muted chat_id: {} user_id: {} muted: {}",
+            chat_id, user_id, muted
+        );
+        let query = if muted {
+            "INSERT OR IGNORE INTO muted_members (chat_id, user_id) VALUES (?, ?)"
+        } else {
+            "DELETE FROM muted_members WHERE chat_id = ? AND user_id = ?"
+        };
+        let result = sqlx::query(query)
+            .bind(chat_id.0)
+            .bind(user_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn migrate_chat(&self, from: ChatId, to: ChatId) -> Result<(), sqlx::Error> {
+        debug!("migrate chat from: {} to: {}", from, to);
+        let mut tx = self.pool.begin().await?;
+        let mut moved = 0;
+        for (table, _) in CHAT_TABLES {
+            // rows already present for new chat win, leftovers of old chat are dropped
+            let result = sqlx::query(&format!(
+                "UPDATE OR IGNORE {} SET chat_id = ? WHERE chat_id = ?",
+                table
+            ))
+            .bind(to.0)
+            .bind(from.0)
+            .execute(&mut *tx)
+            .await?;
+            moved += result.rows_affected();
+            sqlx::query(&format!("DELETE FROM {} WHERE chat_id = ?", table))
+                .bind(from.0)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for table in MESSAGE_TABLES {
+            sqlx::query(&format!("DELETE FROM {} WHERE chat_id = ?", table))
+                .bind(from.0)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        if moved > 0 {
+            info!("migrated chat from: {} to: {} rows: {}", from, to, moved);
+        }
+        Ok(())
+    }
+
+    async fn add_scheduled_ping(&self, ping: &ScheduledPing) -> Result<i64, sqlx::Error> {
+        debug!(
+            "add scheduled ping chat_id: {} schedule: {:?} next_run: {}",
+            ping.chat_id, ping.schedule, ping.next_run
+        );
+        let result = sqlx::query(
+            "INSERT INTO scheduled_pings (chat_id, created_by, schedule, next_run, group_name, text, thread_id) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(ping.chat_id)
+        .bind(&ping.created_by)
+        .bind(&ping.schedule)
+        .bind(ping.next_run)
+        .bind(&ping.group_name)
+        .bind(&ping.text)
+        .bind(ping.thread_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.last_insert_rowid())
+    }
+
+    async fn chat_scheduled_pings(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Vec<ScheduledPing>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT id, chat_id, created_by, schedule, next_run, group_name, text, thread_id
+            FROM scheduled_pings
+            WHERE chat_id = ?
+            ORDER BY next_run",
+        )
+        .bind(chat_id.0)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn due_scheduled_pings(&self, now: i64) -> Result<Vec<ScheduledPing>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT id, chat_id, created_by, schedule, next_run, group_name, text, thread_id
+            FROM scheduled_pings
+            WHERE next_run <= ?
+            ORDER BY next_run",
+        )
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn reschedule_ping(
+        &self,
+        id: i64,
+        old_next_run: i64,
+        next_run: i64,
+    ) -> Result<bool, sqlx::Error> {
+        debug!("reschedule ping id: {} next_run: {}", id, next_run);
+        let result =
+            sqlx::query("UPDATE scheduled_pings SET next_run = ? WHERE id = ? AND next_run = ?")
+                .bind(next_run)
+                .bind(id)
+                .bind(old_next_run)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_scheduled_ping(&self, chat_id: ChatId, id: i64) -> Result<bool, sqlx::Error> {
+        debug!("delete scheduled ping chat_id: {} id: {}", chat_id, id);
+        let result = sqlx::query("DELETE FROM scheduled_pings WHERE chat_id = ? AND id = ?")
+            .bind(chat_id.0)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn chat_settings(&self, chat_id: ChatId) -> Result<ChatSettings, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT
+                ping_cooldown, daily_quota, ping_policy,
+                escalation_interval, escalation_rounds, mentions_per_message,
+                confirm_above
+            FROM chat_settings
+            WHERE chat_id = ?",
+        )
+        .bind(chat_id.0)
+        .fetch_optional(&self.pool)
+        .await
+        .map(Option::unwrap_or_default)
+    }
+
+    async fn save_chat_settings(
+        &self,
+        chat_id: ChatId,
+        settings: &ChatSettings,
+    ) -> Result<(), sqlx::Error> {
+        debug!("save chat settings chat_id: {} {:?}", chat_id, settings);
+        sqlx::query(
+            "INSERT
+            INTO chat_settings (
+                chat_id, ping_cooldown, daily_quota, ping_policy,
+                escalation_interval, escalation_rounds, mentions_per_message,
+                confirm_above
+                )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (chat_id)
+            DO UPDATE SET
+                ping_cooldown = EXCLUDED.ping_cooldown,
+                daily_quota = EXCLUDED.daily_quota,
+                ping_policy = EXCLUDED.ping_policy,
+                escalation_interval = EXCLUDED.escalation_interval,
+                escalation_rounds = EXCLUDED.escalation_rounds,
+                mentions_per_message = EXCLUDED.mentions_per_message,
+                confirm_above = EXCLUDED.confirm_above",
+        )
+        .bind(chat_id.0)
+        .bind(settings.ping_cooldown)
+        .bind(settings.daily_quota)
+        .bind(settings.ping_policy.as_str())
+        .bind(settings.escalation_interval)
+        .bind(settings.escalation_rounds)
+        .bind(settings.mentions_per_message)
+        .bind(settings.confirm_above)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn log_ping(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        sent_at: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR IGNORE
+            INTO ping_log (chat_id, user_id, sent_at)
+            VALUES (?, ?, ?)",
+        )
+        .bind(chat_id.0)
+        .bind(user_id.to_string())
+        .bind(sent_at)
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("DELETE FROM ping_log WHERE sent_at < ?")
+            .bind(sent_at - PING_LOG_TTL)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn last_ping(&self, chat_id: ChatId) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query("SELECT MAX(sent_at) FROM ping_log WHERE chat_id = ?")
+            .bind(chat_id.0)
+            .fetch_one(&self.pool)
+            .await
+            .map(|row| row.get(0))
+    }
+
+    async fn user_pings(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        since: i64,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        sqlx::query(
+            "SELECT sent_at
+            FROM ping_log
+            WHERE chat_id = ? AND user_id = ? AND sent_at >= ?
+            ORDER BY sent_at",
+        )
+        .bind(chat_id.0)
+        .bind(user_id.to_string())
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn set_ping_allowed(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        allowed: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set ping allowed chat_id: {} user_id: {} allowed: {}",
+            chat_id, user_id, allowed
+        );
+        let query = if allowed {
+            "INSERT OR IGNORE INTO ping_allowlist (chat_id, user_id) VALUES (?, ?)"
+        } else {
+            "DELETE FROM ping_allowlist WHERE chat_id = ? AND user_id = ?"
+        };
+        let result = sqlx::query(query)
+            .bind(chat_id.0)
+            .bind(user_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn is_ping_allowed(&self, chat_id: ChatId, user_id: UserId) -> Result<bool, sqlx::Error> {
+        sqlx::query("SELECT 1 FROM ping_allowlist WHERE chat_id = ? AND user_id = ?")
+            .bind(chat_id.0)
+            .bind(user_id.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .map(|row| row.is_some())
+    }
+
+    async fn ping_allowlist(&self, chat_id: ChatId) -> Result<Vec<Member>, sqlx::Error> {
+        sqlx::query_as("SELECT u.* FROM ping_allowlist pa JOIN users u ON pa.user_id = u.user_id WHERE pa.chat_id = ?")
+            .bind(chat_id.0)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn add_sent_ping(&self, ping: &SentPing, targets: &[UserId]) -> Result<(), sqlx::Error> {
+        debug!(
+            "add sent ping chat_id: {} message_id: {} targets: {}",
+            ping.chat_id,
+            ping.message_id,
+            targets.len()
+        );
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT
+            INTO pings (chat_id, message_id, created_by, text, entities, sent_at, rounds_left, next_round, thread_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(ping.chat_id)
+        .bind(ping.message_id)
+        .bind(&ping.created_by)
+        .bind(&ping.text)
+        .bind(&ping.entities)
+        .bind(ping.sent_at)
+        .bind(ping.rounds_left)
+        .bind(ping.next_round)
+        .bind(ping.thread_id)
+        .execute(&mut *tx)
+        .await?;
+        for user_id in targets {
+            sqlx::query("INSERT OR IGNORE INTO ping_targets (chat_id, message_id, user_id) VALUES (?, ?, ?)")
+                .bind(ping.chat_id)
+                .bind(ping.message_id)
+                .bind(user_id.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("DELETE FROM pings WHERE sent_at < ?")
+            .bind(ping.sent_at - SENT_PING_TTL)
+            .execute(&mut *tx)
+            .await?;
+        for table in ["ping_responses", "ping_targets"] {
+            sqlx::query(&format!(
+                "DELETE FROM {0} WHERE NOT EXISTS (SELECT 1 FROM pings p WHERE p.chat_id = {0}.chat_id AND p.message_id = {0}.message_id)",
+                table
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn sent_ping(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Option<SentPing>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT chat_id, message_id, created_by, text, entities, sent_at, rounds_left, next_round, thread_id
+            FROM pings
+            WHERE chat_id = ? AND message_id = ?",
+        )
+        .bind(chat_id.0)
+        .bind(message_id.0)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn set_ping_response(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        user_id: UserId,
+        response: PingResponse,
+        at: i64,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set ping response chat_id: {} message_id: {} user_id: {} response: {:?}",
+            chat_id, message_id, user_id, response
+        );
+        let result = sqlx::query(
+            "INSERT
+            INTO ping_responses (chat_id, message_id, user_id, response, responded_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (chat_id, message_id, user_id)
+            DO UPDATE SET
+                response = EXCLUDED.response,
+                responded_at = EXCLUDED.responded_at
+            WHERE ping_responses.response <> EXCLUDED.response",
+        )
+        .bind(chat_id.0)
+        .bind(message_id.0)
+        .bind(user_id.to_string())
+        .bind(response.as_str())
+        .bind(at)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn ping_responses(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Vec<Responder>, sqlx::Error> {
+        sqlx::query_as("SELECT u.*, pr.response FROM ping_responses pr JOIN users u ON pr.user_id = u.user_id WHERE pr.chat_id = ? AND pr.message_id = ? ORDER BY pr.responded_at")
+            .bind(chat_id.0)
+            .bind(message_id.0)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn due_escalations(&self, now: i64) -> Result<Vec<SentPing>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT chat_id, message_id, created_by, text, entities, sent_at, rounds_left, next_round, thread_id
+            FROM pings
+            WHERE next_round <= ?
+            ORDER BY next_round",
+        )
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn update_escalation(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        old_next_round: Option<i64>,
+        rounds_left: i64,
+        next_round: Option<i64>,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "update escalation chat_id: {} message_id: {} rounds_left: {} next_round: {:?}",
+            chat_id, message_id, rounds_left, next_round
+        );
+        let result = sqlx::query(
+            "UPDATE pings SET rounds_left = ?, next_round = ?
+            WHERE chat_id = ? AND message_id = ? AND next_round IS ?",
+        )
+        .bind(rounds_left)
+        .bind(next_round)
+        .bind(chat_id.0)
+        .bind(message_id.0)
+        .bind(old_next_round)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn silent_targets(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        sqlx::query_as("SELECT u.* FROM ping_targets pt JOIN chat_members cm ON pt.chat_id = cm.chat_id AND pt.user_id = cm.user_id JOIN users u ON pt.user_id = u.user_id WHERE pt.chat_id = ? AND pt.message_id = ? AND NOT EXISTS (SELECT 1 FROM ping_responses pr WHERE pr.chat_id = pt.chat_id AND pr.message_id = pt.message_id AND pr.user_id = pt.user_id) AND NOT EXISTS (SELECT 1 FROM muted_members mm WHERE mm.chat_id = pt.chat_id AND mm.user_id = pt.user_id)")
+            .bind(chat_id.0)
+            .bind(message_id.0)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn set_delivery(&self, user: &User, delivery: Delivery) -> Result<(), sqlx::Error> {
+        debug!("set delivery user_id: {} delivery: {:?}", user.id, delivery);
+        self.save_user(user).await?;
+        sqlx::query(
+            "INSERT
+            INTO user_settings (user_id, delivery)
+            VALUES (?, ?)
+            ON CONFLICT (user_id)
+            DO UPDATE SET delivery = EXCLUDED.delivery",
+        )
+        .bind(user.id.to_string())
+        .bind(delivery.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_timezone(&self, user: &User, utc_offset: Option<i32>) -> Result<(), sqlx::Error> {
+        debug!(
+            "set timezone user_id: {} utc_offset: {:?}",
+            user.id, utc_offset
+        );
+        self.save_user(user).await?;
+        sqlx::query(
+            "INSERT
+            INTO user_settings (user_id, utc_offset)
+            VALUES (?, ?)
+            ON CONFLICT (user_id)
+            DO UPDATE SET utc_offset = EXCLUDED.utc_offset",
+        )
+        .bind(user.id.to_string())
+        .bind(utc_offset)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_quiet_hours(
+        &self,
+        user: &User,
+        quiet_hours: Option<(i32, i32)>,
+    ) -> Result<(), sqlx::Error> {
+        debug!(
+            "set quiet hours user_id: {} quiet_hours: {:?}",
+            user.id, quiet_hours
+        );
+        self.save_user(user).await?;
+        sqlx::query(
+            "INSERT
+            INTO user_settings (user_id, quiet_from, quiet_to)
+            VALUES (?, ?, ?)
+            ON CONFLICT (user_id)
+            DO UPDATE SET quiet_from = EXCLUDED.quiet_from, quiet_to = EXCLUDED.quiet_to",
+        )
+        .bind(user.id.to_string())
+        .bind(quiet_hours.map(|(from, _)| from))
+        .bind(quiet_hours.map(|(_, to)| to))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn user_settings(&self, user_id: UserId) -> Result<UserSettings, sqlx::Error> {
+        let settings = sqlx::query_as(
+            "SELECT delivery, utc_offset, quiet_from, quiet_to
+            FROM user_settings
+            WHERE user_id = ?",
+        )
+        .bind(user_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    async fn chat_user_settings(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Vec<(UserId, UserSettings)>, sqlx::Error> {
+        let rows: Vec<MemberSettings> = sqlx::query_as("SELECT us.user_id, us.delivery, us.utc_offset, us.quiet_from, us.quiet_to FROM user_settings us JOIN chat_members cm ON us.user_id = cm.user_id WHERE cm.chat_id = ?")
+            .bind(chat_id.0)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| Some((UserId(row.user_id.parse().ok()?), row.settings)))
+            .collect())
+    }
+
+    async fn set_ping_trigger(
+        &self,
+        chat_id: ChatId,
+        phrase: &str,
+        add: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set ping trigger chat_id: {} phrase: {:?} add: {}",
+            chat_id, phrase, add
+        );
+        let query = if add {
+            "INSERT OR IGNORE INTO ping_triggers (chat_id, phrase) VALUES (?, ?)"
+        } else {
+            "DELETE FROM ping_triggers WHERE chat_id = ? AND phrase = ?"
+        };
+        let result = sqlx::query(query)
+            .bind(chat_id.0)
+            .bind(phrase)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn ping_triggers(&self, chat_id: ChatId) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT phrase FROM ping_triggers WHERE chat_id = ? ORDER BY phrase")
+            .bind(chat_id.0)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn set_topic_follower(
+        &self,
+        chat_id: ChatId,
+        thread_id: ThreadId,
+        user_id: UserId,
+        follow: bool,
+    ) -> Result<bool, sqlx::Error> {
+        debug!(
+            "set topic follower chat_id: {} thread_id: {} user_id: {} follow: {}",
+            chat_id, thread_id, user_id, follow
+        );
+        let query = if follow {
+            "INSERT OR IGNORE INTO topic_followers (chat_id, thread_id, user_id) VALUES (?, ?, ?)"
+        } else {
+            "DELETE FROM topic_followers WHERE chat_id = ? AND thread_id = ? AND user_id = ?"
+        };
+        let result = sqlx::query(query)
+            .bind(chat_id.0)
+            .bind(thread_id.0 .0)
+            .bind(user_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn topic_followers(
+        &self,
+        chat_id: ChatId,
+        thread_id: ThreadId,
+    ) -> Result<Vec<Member>, sqlx::Error> {
+        sqlx::query_as("SELECT u.* FROM topic_followers tf JOIN chat_members cm ON tf.chat_id = cm.chat_id AND tf.user_id = cm.user_id JOIN users u ON tf.user_id = u.user_id WHERE tf.chat_id = ? AND tf.thread_id = ? AND NOT(u.is_bot) AND NOT EXISTS (SELECT 1 FROM muted_members mm WHERE mm.chat_id = tf.chat_id AND mm.user_id = tf.user_id)")
+            .bind(chat_id.0)
+            .bind(thread_id.0 .0)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn old_members(&self) -> Result<Vec<(i64, String)>, sqlx::Error> {
+        let members: Vec<v01::MemberV01> = sqlx::query_as("SELECT * FROM members")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(members
+            .into_iter()
+            .map(|member| (member.chat_id, member.user_id))
+            .collect())
+    }
+}
@@ -0,0 +1,354 @@
+use chrono::Utc;
+use serde_json::json;
+use teloxide::types::{Chat, ChatId, MessageId, ThreadId, User, UserId};
+
+use super::{ChatSettings, Delivery, PingPolicy, PingResponse, ScheduledPing, SentPing, Storage};
+use crate::config::StorageConfig;
+
+const CHAT: ChatId = ChatId(-1001);
+/// Supergroup [`CHAT`] becomes
+const SUPERGROUP: ChatId = ChatId(-1002);
+
+/// Supergroup with id `id`
+pub(crate) fn chat(id: ChatId) -> Chat {
+    serde_json::from_value(json!({"id": id.0, "type": "supergroup", "title": "Чат"})).unwrap()
+}
+
+/// User with name and username made of `id`
+pub(crate) fn user(id: u64, is_bot: bool) -> User {
+    User {
+        id: UserId(id),
+        is_bot,
+        first_name: format!("User{}", id),
+        last_name: None,
+        username: Some(format!("user{}", id)),
+        language_code: None,
+        is_premium: false,
+        added_to_attachment_menu: false,
+    }
+}
+
+fn ids(members: &[super::Member]) -> Vec<&str> {
+    let mut ids: Vec<_> = members
+        .iter()
+        .map(|member| member.user_id.as_str())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Checks that everything [`super::Backend`] saves reads back the same,
+/// `storage` has to be empty
+async fn round_trips(storage: &Storage) {
+    let now = Utc::now().timestamp();
+    let chat = chat(CHAT);
+    let (alice, bob, bot) = (user(1, false), user(2, false), user(3, true));
+
+    // members
+    for user in [&alice, &bob, &bot] {
+        storage.new_member(&chat, user).await.unwrap();
+    }
+    assert_eq!(ids(&storage.chat_members(CHAT).await.unwrap()), ["1", "2"]);
+    assert_eq!(storage.chat_member_ids(CHAT).await.unwrap().len(), 3);
+    assert_eq!(storage.chat_members_count(CHAT).await.unwrap(), 2);
+    assert_eq!(storage.tracked_chats().await.unwrap(), [CHAT]);
+    assert!(storage.set_muted(CHAT, bob.id, true).await.unwrap());
+    assert!(!storage.set_muted(CHAT, bob.id, true).await.unwrap());
+    assert_eq!(ids(&storage.chat_members(CHAT).await.unwrap()), ["1"]);
+    assert!(storage.set_muted(CHAT, bob.id, false).await.unwrap());
+    storage.member_seen(&chat, &alice, now).await.unwrap();
+    assert_eq!(
+        ids(&storage.active_members(CHAT, now - 60).await.unwrap()),
+        ["1"]
+    );
+
+    // groups
+    assert!(storage.join_group(CHAT, alice.id, "dev").await.unwrap());
+    assert!(!storage.join_group(CHAT, alice.id, "dev").await.unwrap());
+    assert!(storage.join_group(CHAT, bob.id, "dev").await.unwrap());
+    assert_eq!(
+        ids(&storage.group_members(CHAT, "dev").await.unwrap()),
+        ["1", "2"]
+    );
+    assert_eq!(
+        storage.chat_groups(CHAT).await.unwrap(),
+        [("dev".to_owned(), 2)]
+    );
+    assert!(storage.leave_group(CHAT, bob.id, "dev").await.unwrap());
+    assert!(!storage.leave_group(CHAT, bob.id, "dev").await.unwrap());
+    assert_eq!(
+        ids(&storage.group_members(CHAT, "dev").await.unwrap()),
+        ["1"]
+    );
+
+    // settings
+    let settings = ChatSettings {
+        ping_cooldown: 1,
+        daily_quota: 2,
+        ping_policy: PingPolicy::Allowlist,
+        escalation_interval: 3,
+        escalation_rounds: 4,
+        mentions_per_message: 5,
+        confirm_above: 6,
+    };
+    storage.save_chat_settings(CHAT, &settings).await.unwrap();
+    assert_eq!(
+        format!("{:?}", storage.chat_settings(CHAT).await.unwrap()),
+        format!("{:?}", settings)
+    );
+
+    // ping log and allowlist
+    storage.log_ping(CHAT, alice.id, now).await.unwrap();
+    assert_eq!(storage.last_ping(CHAT).await.unwrap(), Some(now));
+    assert_eq!(
+        storage.user_pings(CHAT, alice.id, now - 60).await.unwrap(),
+        [now]
+    );
+    assert!(storage.set_ping_allowed(CHAT, bob.id, true).await.unwrap());
+    assert!(storage.is_ping_allowed(CHAT, bob.id).await.unwrap());
+    assert!(!storage.is_ping_allowed(CHAT, alice.id).await.unwrap());
+    assert_eq!(ids(&storage.ping_allowlist(CHAT).await.unwrap()), ["2"]);
+
+    // scheduled pings
+    let scheduled = ScheduledPing {
+        id: 0,
+        chat_id: CHAT.0,
+        created_by: alice.id.to_string(),
+        schedule: Some("daily 10:00".to_owned()),
+        next_run: now,
+        group_name: Some("dev".to_owned()),
+        text: "стендап".to_owned(),
+        thread_id: None,
+    };
+    let id = storage.add_scheduled_ping(&scheduled).await.unwrap();
+    let due = storage.due_scheduled_pings(now).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].id, id);
+    assert_eq!(due[0].text, "стендап");
+    assert!(storage.reschedule_ping(id, now, now + 60).await.unwrap());
+    // already moved, by another instance
+    assert!(!storage.reschedule_ping(id, now, now + 120).await.unwrap());
+    assert!(storage.due_scheduled_pings(now).await.unwrap().is_empty());
+    assert!(storage.delete_scheduled_ping(CHAT, id).await.unwrap());
+    assert!(!storage.delete_scheduled_ping(CHAT, id).await.unwrap());
+    storage.add_scheduled_ping(&scheduled).await.unwrap();
+
+    // sent pings, with entities and from before they were kept
+    let message_id = MessageId(10);
+    let ping = SentPing {
+        chat_id: CHAT.0,
+        message_id: message_id.0,
+        created_by: Some(alice.id.to_string()),
+        text: "Пинг".to_owned(),
+        entities: Some("[]".to_owned()),
+        sent_at: now,
+        rounds_left: 2,
+        next_round: Some(now),
+        thread_id: None,
+    };
+    storage
+        .add_sent_ping(&ping, &[alice.id, bob.id])
+        .await
+        .unwrap();
+    let legacy = SentPing {
+        message_id: 11,
+        entities: None,
+        next_round: None,
+        ..ping.clone()
+    };
+    storage.add_sent_ping(&legacy, &[bob.id]).await.unwrap();
+    let sent = storage.sent_ping(CHAT, message_id).await.unwrap().unwrap();
+    assert_eq!(sent.entities.as_deref(), Some("[]"));
+    assert_eq!(sent.created_by, ping.created_by);
+    let sent = storage
+        .sent_ping(CHAT, MessageId(11))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(sent.entities, None);
+    assert!(storage
+        .set_ping_response(CHAT, message_id, alice.id, PingResponse::Here, now)
+        .await
+        .unwrap());
+    assert!(!storage
+        .set_ping_response(CHAT, message_id, alice.id, PingResponse::Here, now)
+        .await
+        .unwrap());
+    let responses = storage.ping_responses(CHAT, message_id).await.unwrap();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].member.user_id, "1");
+    assert_eq!(responses[0].response, PingResponse::Here);
+    assert_eq!(
+        ids(&storage.silent_targets(CHAT, message_id).await.unwrap()),
+        ["2"]
+    );
+    let due = storage.due_escalations(now).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].message_id, message_id.0);
+    assert!(storage
+        .update_escalation(CHAT, message_id, Some(now), 1, None)
+        .await
+        .unwrap());
+    // already updated, by another instance
+    assert!(!storage
+        .update_escalation(CHAT, message_id, Some(now), 0, None)
+        .await
+        .unwrap());
+    assert!(storage.due_escalations(now).await.unwrap().is_empty());
+
+    // triggers and topics
+    assert!(storage
+        .set_ping_trigger(CHAT, "все сюда", true)
+        .await
+        .unwrap());
+    assert!(!storage
+        .set_ping_trigger(CHAT, "все сюда", true)
+        .await
+        .unwrap());
+    assert_eq!(storage.ping_triggers(CHAT).await.unwrap(), ["все сюда"]);
+    let topic = ThreadId(MessageId(5));
+    assert!(storage
+        .set_topic_follower(CHAT, topic, bob.id, true)
+        .await
+        .unwrap());
+    assert_eq!(
+        ids(&storage.topic_followers(CHAT, topic).await.unwrap()),
+        ["2"]
+    );
+
+    // user settings
+    storage.set_delivery(&bob, Delivery::Dm).await.unwrap();
+    storage.set_timezone(&bob, Some(3600)).await.unwrap();
+    storage
+        .set_quiet_hours(&bob, Some((22 * 60, 8 * 60)))
+        .await
+        .unwrap();
+    let settings = storage.user_settings(bob.id).await.unwrap();
+    assert_eq!(settings.delivery, Delivery::Dm);
+    assert_eq!(settings.utc_offset, Some(3600));
+    assert_eq!(settings.quiet_hours(), Some((22 * 60, 8 * 60)));
+    let chat_settings = storage.chat_user_settings(CHAT).await.unwrap();
+    assert_eq!(chat_settings.len(), 1);
+    assert_eq!(chat_settings[0].0, bob.id);
+    assert_eq!(
+        storage.user_settings(alice.id).await.unwrap().delivery,
+        Delivery::Group
+    );
+
+    // group becoming supergroup keeps everything but sent pings
+    storage.migrate_chat(CHAT, SUPERGROUP).await.unwrap();
+    assert!(storage.chat_member_ids(CHAT).await.unwrap().is_empty());
+    assert_eq!(
+        ids(&storage.chat_members(SUPERGROUP).await.unwrap()),
+        ["1", "2"]
+    );
+    assert_eq!(
+        ids(&storage.group_members(SUPERGROUP, "dev").await.unwrap()),
+        ["1"]
+    );
+    assert_eq!(
+        storage.chat_settings(SUPERGROUP).await.unwrap().daily_quota,
+        2
+    );
+    assert_eq!(
+        storage.chat_settings(CHAT).await.unwrap().daily_quota,
+        ChatSettings::default().daily_quota
+    );
+    assert!(storage.is_ping_allowed(SUPERGROUP, bob.id).await.unwrap());
+    let scheduled = storage.chat_scheduled_pings(SUPERGROUP).await.unwrap();
+    assert_eq!(scheduled.len(), 1);
+    assert_eq!(scheduled[0].chat_id, SUPERGROUP.0);
+    assert_eq!(
+        storage.ping_triggers(SUPERGROUP).await.unwrap(),
+        ["все сюда"]
+    );
+    assert_eq!(
+        ids(&storage.topic_followers(SUPERGROUP, topic).await.unwrap()),
+        ["2"]
+    );
+    for chat_id in [CHAT, SUPERGROUP] {
+        assert!(storage
+            .sent_ping(chat_id, message_id)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .ping_responses(chat_id, message_id)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .silent_targets(chat_id, message_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    storage.delete_member(SUPERGROUP, alice.id).await.unwrap();
+    assert_eq!(ids(&storage.chat_members(SUPERGROUP).await.unwrap()), ["2"]);
+}
+
+#[tokio::test]
+async fn memory_round_trips() {
+    let storage = Storage::init(&StorageConfig::Memory).await.unwrap();
+    round_trips(&storage).await;
+}
+
+#[tokio::test]
+async fn sqlite_round_trips() {
+    let file = std::env::temp_dir().join(format!("tgpingbot-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let storage = Storage::init(&StorageConfig::Sqlite(file.clone()))
+        .await
+        .unwrap();
+    round_trips(&storage).await;
+    // migrations are applied once
+    drop(storage);
+    Storage::init(&StorageConfig::Sqlite(file.clone()))
+        .await
+        .unwrap();
+    let _ = std::fs::remove_file(&file);
+}
+
+/// Runs against database at `TEST_POSTGRES_URL`, in a schema of its own
+/// dropped afterwards
+#[tokio::test]
+#[ignore = "needs PostgreSQL at TEST_POSTGRES_URL"]
+async fn postgres_round_trips() {
+    let url = std::env::var("TEST_POSTGRES_URL").expect("TEST_POSTGRES_URL is not set");
+    let schema = format!("tgpingbot_test_{}", std::process::id());
+    let pool = sqlx::PgPool::connect(&url).await.unwrap();
+    sqlx::query(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {}", schema))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let config = StorageConfig::Postgres(format!(
+        "{}{}options[search_path]={}",
+        url, separator, schema
+    ));
+    // instances started together wait for each other to migrate
+    let (first, second) = tokio::join!(Storage::init(&config), Storage::init(&config));
+    let storage = first.unwrap();
+    drop(second.unwrap());
+    let version: i64 = sqlx::query_scalar(&format!(
+        "SELECT MAX(version) FROM {}.schema_version",
+        schema
+    ))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(version as usize, super::migrations::POSTGRES.len());
+
+    round_trips(&storage).await;
+    drop(storage);
+    sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema))
+        .execute(&pool)
+        .await
+        .unwrap();
+}